@@ -1,53 +1,146 @@
-use indicatif::ProgressBar;
 use postgres::types::ToSql;
 use quick_xml::events::Event;
-use std::{collections::HashMap, error::Error, str};
-
-use crate::db::{write_artists, DbOpt, SqlSerialization};
-use crate::parser::Parser;
-
-#[derive(Clone, Debug)]
+use serde::Serialize;
+use std::{mem, rc::Rc, str};
+
+use crate::db::SqlSerialization;
+use crate::error::Result;
+use crate::parser::{
+    attribute, Coverage, DebugSampler, Entity, ParserOpt, Record, RecordParser, TextDecoder,
+};
+use crate::sink::{rows, Sink, TableWriter};
+use crate::transform::{apply_all, dedup_arrays, Transform, Transformable};
+
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct Artist {
-    pub id: i32,
-    pub name: String,
-    pub real_name: String,
-    pub profile: String,
-    pub data_quality: String,
+    pub id: i64,
+    pub name: Option<String>,
+    pub real_name: Option<String>,
+    pub profile: Option<String>,
+    pub data_quality: Option<String>,
     pub name_variations: Vec<String>,
     pub urls: Vec<String>,
     pub aliases: Vec<String>,
     pub members: Vec<String>,
+    pub groups: Vec<String>,
+    pub source_offset: Option<i64>,
+    pub artist_members: Vec<ArtistMember>,
+    pub artist_aliases: Vec<ArtistAlias>,
 }
 
 impl SqlSerialization for Artist {
-    fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
-        let row: Vec<&'_ (dyn ToSql + Sync)> = vec![
-            &self.id,
-            &self.name,
-            &self.real_name,
-            &self.profile,
-            &self.data_quality,
-            &self.name_variations,
-            &self.urls,
-            &self.aliases,
-            &self.members,
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("id", &self.id),
+            ("name", &self.name),
+            ("real_name", &self.real_name),
+            ("profile", &self.profile),
+            ("data_quality", &self.data_quality),
+            ("name_variations", &self.name_variations),
+            ("urls", &self.urls),
+            ("aliases", &self.aliases),
+            ("members", &self.members),
+            ("groups", &self.groups),
+            ("source_offset", &self.source_offset),
         ];
         row
     }
 }
 
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ArtistMember {
+    pub artist_id: i64,
+    pub member_id: i64,
+    pub member_name: String,
+}
+
+impl SqlSerialization for ArtistMember {
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("artist_id", &self.artist_id),
+            ("member_id", &self.member_id),
+            ("member_name", &self.member_name),
+        ];
+        row
+    }
+}
+
+impl ArtistMember {
+    pub fn new() -> Self {
+        ArtistMember {
+            artist_id: 0,
+            member_id: 0,
+            member_name: String::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ArtistAlias {
+    pub artist_id: i64,
+    pub alias_id: i64,
+    pub alias_name: String,
+}
+
+impl SqlSerialization for ArtistAlias {
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("artist_id", &self.artist_id),
+            ("alias_id", &self.alias_id),
+            ("alias_name", &self.alias_name),
+        ];
+        row
+    }
+}
+
+impl ArtistAlias {
+    pub fn new() -> Self {
+        ArtistAlias {
+            artist_id: 0,
+            alias_id: 0,
+            alias_name: String::new(),
+        }
+    }
+}
+
+impl Transformable for Artist {
+    fn text_field(&mut self, name: &str) -> Option<&mut String> {
+        match name {
+            "name" => self.name.as_mut(),
+            "real_name" => self.real_name.as_mut(),
+            "profile" => self.profile.as_mut(),
+            "data_quality" => self.data_quality.as_mut(),
+            _ => None,
+        }
+    }
+
+    fn text_arrays(&mut self) -> Vec<&mut Vec<String>> {
+        vec![
+            &mut self.name_variations,
+            &mut self.urls,
+            &mut self.aliases,
+            &mut self.members,
+            &mut self.groups,
+        ]
+    }
+}
+
 impl Artist {
     pub fn new() -> Self {
         Artist {
             id: 0,
-            name: String::new(),
-            real_name: String::new(),
-            profile: String::new(),
-            data_quality: String::new(),
+            name: None,
+            real_name: None,
+            profile: None,
+            data_quality: None,
             name_variations: Vec::new(),
             urls: Vec::new(),
             aliases: Vec::new(),
             members: Vec::new(),
+            groups: Vec::new(),
+            source_offset: None,
+            artist_members: Vec::new(),
+            artist_aliases: Vec::new(),
         }
     }
 }
@@ -61,57 +154,111 @@ enum ParserState {
     Profile,
     DataQuality,
     NameVariations,
+    NameVariation,
     Url,
     Urls,
     Alias,
     Aliases,
     Member,
     Members,
+    Group,
+    Groups,
 }
 
-pub struct ArtistsParser<'a> {
+impl Record for Artist {
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn write(sink: &mut dyn Sink, batch: Vec<Self>) -> Result<()> {
+        sink.take_artists(batch)
+    }
+
+    fn write_tables(batch: &[Self], tables: &mut dyn TableWriter) -> Result<()> {
+        tables.write_rows("artist", &mut rows(batch))?;
+        tables.write_rows(
+            "artist_member",
+            &mut rows(batch.iter().flat_map(|a| &a.artist_members)),
+        )?;
+        tables.write_rows(
+            "artist_alias",
+            &mut rows(batch.iter().flat_map(|a| &a.artist_aliases)),
+        )
+    }
+}
+
+pub struct ArtistsParser {
     state: ParserState,
-    artists: HashMap<i32, Artist>,
     current_artist: Artist,
-    pb: ProgressBar,
-    db_opts: &'a DbOpt,
+    current_member: ArtistMember,
+    current_alias: ArtistAlias,
+    records: u64,
+    sampler: DebugSampler,
+    transforms: Vec<Transform>,
+    coverage: Coverage,
+    with_source_offset: bool,
+    dedup_arrays: bool,
+    text: Rc<TextDecoder>,
 }
 
-impl<'a> ArtistsParser<'a> {
-    pub fn new(db_opts: &'a DbOpt) -> Self {
+impl RecordParser for ArtistsParser {
+    type Record = Artist;
+    const ENTITY: Entity = Entity::Artist;
+
+    fn new(parser_opts: &ParserOpt, text: Rc<TextDecoder>) -> Self {
         ArtistsParser {
             state: ParserState::Artist,
-            artists: HashMap::new(),
             current_artist: Artist::new(),
-            pb: ProgressBar::new(7993954),
-            db_opts,
+            current_member: ArtistMember::new(),
+            current_alias: ArtistAlias::new(),
+            records: 0,
+            sampler: DebugSampler::new(parser_opts.debug_sample),
+            transforms: parser_opts.transforms.clone(),
+            coverage: Coverage::new(parser_opts),
+            with_source_offset: parser_opts.with_source_offset,
+            dedup_arrays: parser_opts.dedup_arrays,
+            text,
         }
     }
-}
 
-impl<'a> Parser<'a> for ArtistsParser<'a> {
-    fn new(&self, db_opts: &'a DbOpt) -> Self {
-        ArtistsParser {
-            state: ParserState::Artist,
-            artists: HashMap::new(),
-            current_artist: Artist::new(),
-            pb: ProgressBar::new(7993954),
-            db_opts,
-        }
+    fn records(&self) -> u64 {
+        self.records
+    }
+
+    fn coverage(&self) -> &Coverage {
+        &self.coverage
+    }
+
+    fn discard(&mut self) {
+        self.records += 1;
+        self.state = ParserState::Artist;
+    }
+
+    fn current_id(&self) -> i64 {
+        self.current_artist.id
+    }
+
+    fn process(&mut self, ev: Event) -> Result<Option<Artist>> {
+        self.read(ev)
+            .map_err(|e| e.in_record("artist", self.current_artist.id))
     }
-    fn process(&mut self, ev: Event) -> Result<(), Box<dyn Error>> {
+}
+
+impl ArtistsParser {
+    fn read(&mut self, ev: Event) -> Result<Option<Artist>> {
+        let mut ended = None;
         self.state = match self.state {
-            ParserState::Artist => {
-                match ev {
-                    Event::Start(e) if e.local_name() == b"artist" => {
-                        self.current_artist.name_variations = Vec::new();
-                        self.current_artist.urls = Vec::new();
-                        self.current_artist.aliases = Vec::new();
-                        self.current_artist.members = Vec::new();
-                        ParserState::Artist
+            ParserState::Artist => match ev {
+                Event::Start(e) if e.local_name() == b"artist" => {
+                    self.current_artist = Artist::new();
+                    if self.with_source_offset {
+                        self.current_artist.source_offset = Some(self.text.event_start() as i64);
                     }
+                    ParserState::Artist
+                }
 
-                    Event::Start(e) => match e.local_name() {
+                Event::Start(e) => {
+                    let state = match e.local_name() {
                         b"id" => ParserState::Id,
                         b"name" => ParserState::Name,
                         b"realname" => ParserState::RealName,
@@ -121,35 +268,46 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
                         b"namevariations" => ParserState::NameVariations,
                         b"aliases" => ParserState::Aliases,
                         b"members" => ParserState::Members,
+                        b"groups" => ParserState::Groups,
                         _ => ParserState::Artist,
-                    },
-
-                    Event::End(e) if e.local_name() == b"artist" => {
-                        self.artists
-                            .entry(self.current_artist.id)
-                            .or_insert(self.current_artist.clone());
-                        if self.artists.len() >= self.db_opts.batch_size {
-                            // use drain? https://doc.rust-lang.org/std/collections/struct.HashMap.html#examples-13
-                            write_artists(self.db_opts, &self.artists)?;
-                            self.artists = HashMap::new();
-                        }
-                        self.pb.inc(1);
-                        ParserState::Artist
-                    }
+                    };
+                    self.coverage
+                        .saw(e.local_name(), !matches!(state, ParserState::Artist));
+                    state
+                }
 
-                    Event::End(e) if e.local_name() == b"artists" => {
-                        // write to db remainder of artists
-                        write_artists(self.db_opts, &self.artists)?;
-                        ParserState::Artist
-                    }
+                Event::End(e)
+                    if e.local_name() == b"artist"
+                        && self.text.skip_record("artist", self.current_artist.id) =>
+                {
+                    self.discard();
+                    ParserState::Artist
+                }
 
-                    _ => ParserState::Artist,
+                Event::End(e) if e.local_name() == b"artist" => {
+                    apply_all(&self.transforms, &mut self.current_artist);
+                    if self.dedup_arrays {
+                        dedup_arrays(&mut self.current_artist);
+                    }
+                    self.sampler.sample(&self.current_artist);
+                    let id = self.current_artist.id;
+                    ended = Some(mem::replace(
+                        &mut self.current_artist,
+                        Artist {
+                            id,
+                            ..Artist::new()
+                        },
+                    ));
+                    self.records += 1;
+                    ParserState::Artist
                 }
-            }
+
+                _ => ParserState::Artist,
+            },
 
             ParserState::Id => match ev {
                 Event::Text(e) => {
-                    self.current_artist.id = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_artist.id = str::parse(&self.text.decode(&e)?)?;
                     ParserState::Id
                 }
 
@@ -160,7 +318,7 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
 
             ParserState::Name => match ev {
                 Event::Text(e) => {
-                    self.current_artist.name = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_artist.name = Some(self.text.decode(&e)?);
                     ParserState::Name
                 }
 
@@ -171,7 +329,7 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
 
             ParserState::RealName => match ev {
                 Event::Text(e) => {
-                    self.current_artist.real_name = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_artist.real_name = Some(self.text.decode(&e)?);
                     ParserState::RealName
                 }
 
@@ -182,7 +340,7 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
 
             ParserState::Profile => match ev {
                 Event::Text(e) => {
-                    self.current_artist.profile = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_artist.profile = Some(self.text.decode(&e)?);
                     ParserState::Profile
                 }
 
@@ -193,8 +351,7 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
 
             ParserState::DataQuality => match ev {
                 Event::Text(e) => {
-                    self.current_artist.data_quality =
-                        str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_artist.data_quality = Some(self.text.decode(&e)?);
                     ParserState::DataQuality
                 }
 
@@ -203,6 +360,25 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
                 _ => ParserState::DataQuality,
             },
 
+            ParserState::NameVariations => match ev {
+                Event::Start(e) if e.local_name() == b"name" => ParserState::NameVariation,
+
+                Event::End(e) if e.local_name() == b"namevariations" => ParserState::Artist,
+
+                _ => ParserState::NameVariations,
+            },
+
+            ParserState::NameVariation => match ev {
+                Event::Text(e) => {
+                    self.current_artist
+                        .name_variations
+                        .extend(str::parse(&self.text.decode(&e)?));
+                    ParserState::NameVariations
+                }
+
+                _ => ParserState::NameVariations,
+            },
+
             ParserState::Urls => match ev {
                 Event::Start(e) if e.local_name() == b"url" => ParserState::Url,
 
@@ -215,7 +391,7 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
                 Event::Text(e) => {
                     self.current_artist
                         .urls
-                        .extend(str::parse(str::from_utf8(&e.unescaped()?)?));
+                        .extend(str::parse(&self.text.decode(&e)?));
                     ParserState::Urls
                 }
 
@@ -223,46 +399,95 @@ impl<'a> Parser<'a> for ArtistsParser<'a> {
             },
 
             ParserState::Aliases => match ev {
-                Event::Start(e) if e.local_name() == b"alias" => ParserState::Alias,
+                Event::Start(e) if e.local_name() == b"name" => {
+                    self.current_alias = ArtistAlias::new();
+                    self.current_alias.artist_id = self.current_artist.id;
+                    if let Some(id) = attribute(&e, b"id")? {
+                        self.current_alias.alias_id = str::parse(&id)?;
+                    }
+                    ParserState::Alias
+                }
 
                 Event::End(e) if e.local_name() == b"aliases" => ParserState::Artist,
 
-                _ => ParserState::Artist,
+                _ => ParserState::Aliases,
             },
 
             ParserState::Alias => match ev {
                 Event::Text(e) => {
+                    self.current_alias.alias_name = self.text.decode(&e)?;
                     self.current_artist
-                        .members
-                        .extend(str::parse(str::from_utf8(&e.unescaped()?)?));
+                        .aliases
+                        .push(self.current_alias.alias_name.clone());
+                    ParserState::Alias
+                }
+
+                Event::End(e) if e.local_name() == b"name" => {
+                    self.sampler.sample(&self.current_alias);
+                    self.current_artist
+                        .artist_aliases
+                        .push(mem::replace(&mut self.current_alias, ArtistAlias::new()));
                     ParserState::Aliases
                 }
 
-                _ => ParserState::Aliases,
+                _ => ParserState::Alias,
             },
 
             ParserState::Members => match ev {
-                Event::Start(e) if e.local_name() == b"member" => ParserState::Member,
+                Event::Start(e) if e.local_name() == b"name" => {
+                    self.current_member = ArtistMember::new();
+                    self.current_member.artist_id = self.current_artist.id;
+                    if let Some(id) = attribute(&e, b"id")? {
+                        self.current_member.member_id = str::parse(&id)?;
+                    }
+                    ParserState::Member
+                }
 
                 Event::End(e) if e.local_name() == b"members" => ParserState::Artist,
 
-                _ => ParserState::Artist,
+                _ => ParserState::Members,
             },
 
             ParserState::Member => match ev {
                 Event::Text(e) => {
+                    self.current_member.member_name = str::parse(&self.text.decode(&e)?)?;
                     self.current_artist
                         .members
-                        .extend(str::parse(str::from_utf8(&e.unescaped()?)?));
+                        .push(self.current_member.member_name.clone());
+                    ParserState::Member
+                }
+
+                Event::End(e) if e.local_name() == b"name" => {
+                    self.sampler.sample(&self.current_member);
+                    self.current_artist
+                        .artist_members
+                        .push(mem::replace(&mut self.current_member, ArtistMember::new()));
                     ParserState::Members
                 }
 
-                _ => ParserState::Members,
+                _ => ParserState::Member,
+            },
+
+            ParserState::Groups => match ev {
+                Event::Start(e) if e.local_name() == b"name" => ParserState::Group,
+
+                Event::End(e) if e.local_name() == b"groups" => ParserState::Artist,
+
+                _ => ParserState::Groups,
             },
 
-            _ => ParserState::Members,
+            ParserState::Group => match ev {
+                Event::Text(e) => {
+                    self.current_artist.groups.push(self.text.decode(&e)?);
+                    ParserState::Group
+                }
+
+                Event::End(e) if e.local_name() == b"name" => ParserState::Groups,
+
+                _ => ParserState::Group,
+            },
         };
 
-        Ok(())
+        Ok(ended)
     }
 }
@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use crate::error::Result;
+
+/// How far the files of a load got, written with `--checkpoint` after every
+/// batch so an interrupted load can continue where it stopped.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub files: BTreeMap<String, FilePosition>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilePosition {
+    /// Byte offset in the decompressed dump right after the last written record.
+    pub offset: usize,
+    /// Id of the last written record.
+    pub last_id: i64,
+    pub complete: bool,
+}
+
+impl Checkpoint {
+    /// Read the checkpoint, a missing file is an empty checkpoint.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(json) => Ok(serde_json::from_str(&json).map_err(io::Error::from)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Checkpoint::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replace the checkpoint file, through a rename so it is never left
+    /// half written.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::from)?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::artist::Artist;
+use crate::db::{DbOpt, SqlSerialization, TableColumns, Value};
+use crate::error::Result;
+use crate::label::Label;
+use crate::master::Master;
+use crate::parser::Record;
+use crate::release::Release;
+use crate::sink::{Sink, TableWriter};
+
+/// Writes the tables to one CSV file each in a directory, `release.csv`,
+/// `release_label.csv` and so on, for `--output-format csv`.
+///
+/// The header of a file names the columns the loader copies into the table.
+/// NULL is an empty field and an empty text a quoted one, like Postgres
+/// reads them with `COPY ... (FORMAT csv, HEADER)`.
+pub struct CsvSink {
+    dir: PathBuf,
+    columns: TableColumns,
+    array_delimiter: Option<String>,
+    files: HashMap<&'static str, BufWriter<File>>,
+    rows: BTreeMap<String, u64>,
+}
+
+impl CsvSink {
+    /// Create the directory if it is missing. The file of a table is
+    /// created, or replaced, with the first batch of its entity.
+    ///
+    /// Arrays are written as a Postgres array literal like `{Rock,"Hard Rock"}`,
+    /// or their elements joined with `array_delimiter` when one is given.
+    pub fn new(dir: &Path, db_opts: &DbOpt, array_delimiter: Option<String>) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(CsvSink {
+            dir: dir.to_path_buf(),
+            columns: TableColumns::new(db_opts),
+            array_delimiter,
+            files: HashMap::new(),
+            rows: BTreeMap::new(),
+        })
+    }
+
+    fn field(&self, value: &Value) -> String {
+        match value {
+            Value::Null => String::new(),
+            Value::Int(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Text(text) => quoted(text),
+            Value::TextArray(elements) => match &self.array_delimiter {
+                Some(delimiter) => quoted(&elements.join(delimiter)),
+                None => quoted(&array_literal(elements)),
+            },
+        }
+    }
+}
+
+impl TableWriter for CsvSink {
+    fn write_rows(
+        &mut self,
+        table: &'static str,
+        rows: &mut dyn Iterator<Item = &dyn SqlSerialization>,
+    ) -> Result<()> {
+        if !self.files.contains_key(table) {
+            let mut file = BufWriter::new(File::create(self.dir.join(format!("{}.csv", table)))?);
+            let header: Vec<&str> = self
+                .columns
+                .columns(table)
+                .iter()
+                .map(|(column, _)| *column)
+                .collect();
+            writeln!(file, "{}", header.join(","))?;
+            self.files.insert(table, file);
+        }
+        let mut line = String::new();
+        let mut written = 0;
+        for row in rows {
+            line.clear();
+            for (i, value) in self.columns.values(table, row)?.iter().enumerate() {
+                if i > 0 {
+                    line.push(',');
+                }
+                line.push_str(&self.field(value));
+            }
+            line.push('\n');
+            self.files
+                .get_mut(table)
+                .unwrap()
+                .write_all(line.as_bytes())?;
+            written += 1;
+        }
+        *self.rows.entry(table.to_string()).or_insert(0) += written;
+        Ok(())
+    }
+}
+
+impl Sink for CsvSink {
+    fn write_artists(&mut self, batch: &[Artist]) -> Result<()> {
+        Artist::write_tables(batch, self)
+    }
+
+    fn write_labels(&mut self, batch: &[Label]) -> Result<()> {
+        Label::write_tables(batch, self)
+    }
+
+    fn write_masters(&mut self, batch: &[Master]) -> Result<()> {
+        Master::write_tables(batch, self)
+    }
+
+    fn write_releases(&mut self, batch: &[Release]) -> Result<()> {
+        Release::write_tables(batch, self)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for file in self.files.values_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    fn row_counts(&self) -> BTreeMap<String, u64> {
+        self.rows.clone()
+    }
+}
+
+/// A CSV field holding the text, quoted when it has to be. Empty text is
+/// quoted as well, an empty field being NULL, and so is `\.`, which ends
+/// the data of a COPY.
+fn quoted(text: &str) -> String {
+    let quote = text.is_empty() || text == "\\." || text.contains([',', '"', '\n', '\r']);
+    if quote {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// The elements as a Postgres array literal, quoting the ones Postgres would
+/// not read back as they are.
+fn array_literal(elements: &[String]) -> String {
+    let elements: Vec<String> = elements
+        .iter()
+        .map(|element| {
+            let quote = element.is_empty()
+                || element.eq_ignore_ascii_case("NULL")
+                || element.contains(|c: char| {
+                    matches!(c, '{' | '}' | ',' | '"' | '\\') || c.is_whitespace()
+                });
+            if quote {
+                format!("\"{}\"", element.replace('\\', "\\\\").replace('"', "\\\""))
+            } else {
+                element.clone()
+            }
+        })
+        .collect();
+    format!("{{{}}}", elements.join(","))
+}
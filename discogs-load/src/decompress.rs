@@ -0,0 +1,67 @@
+use std::{
+    io::{self, Read},
+    sync::mpsc::{sync_channel, Receiver},
+    thread,
+};
+
+/// Bytes decompressed at once by the decompression thread.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks the decompression thread may run ahead of the parser.
+const CHUNKS_AHEAD: usize = 16;
+
+/// Reads from `inner` on its own thread, so decompressing the dump overlaps
+/// with parsing it.
+pub struct ThreadedReader {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    pos: usize,
+}
+
+impl ThreadedReader {
+    pub fn new<R: Read + Send + 'static>(mut inner: R) -> Self {
+        let (sender, chunks) = sync_channel(CHUNKS_AHEAD);
+        // the thread ends at the end of the input, on an error or once the
+        // reader is dropped
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; CHUNK_SIZE];
+            match inner.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => {
+                    chunk.truncate(read);
+                    if sender.send(Ok(chunk)).is_err() {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                    break;
+                }
+            }
+        });
+        ThreadedReader {
+            chunks,
+            chunk: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for ThreadedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.chunk.len() {
+            match self.chunks.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk?;
+                    self.pos = 0;
+                }
+                // the thread hung up, the input is exhausted
+                Err(_) => return Ok(0),
+            }
+        }
+        let read = buf.len().min(self.chunk.len() - self.pos);
+        buf[..read].copy_from_slice(&self.chunk[self.pos..self.pos + read]);
+        self.pos += read;
+        Ok(read)
+    }
+}
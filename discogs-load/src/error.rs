@@ -0,0 +1,101 @@
+use std::{convert::Infallible, io, num::ParseIntError, str::Utf8Error};
+use thiserror::Error;
+
+pub type Result<T, E = DiscogsLoadError> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum DiscogsLoadError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("XML error: {0}")]
+    Xml(#[from] quick_xml::Error),
+    #[error("Parquet error: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[error("Database error: {}", db_message(.0))]
+    Db(#[from] postgres::Error),
+    #[error("Database pool error: {0}")]
+    Pool(#[from] r2d2_postgres::r2d2::Error),
+    #[error("SQLite error: {0}")]
+    Sqlite(#[from] rusqlite::Error),
+    #[error("Could not encode a row of {table}: {message}")]
+    Encode { table: String, message: String },
+    /// A text node or attribute that is not a valid value, turned into
+    /// `Parse` once the record it belongs to is known.
+    #[error("Invalid value: {0}")]
+    Value(String),
+    #[error("Could not parse {entity} {id}: {message}")]
+    Parse {
+        entity: &'static str,
+        id: i64,
+        message: String,
+    },
+    #[error("{0}")]
+    Config(String),
+    /// The tables differ from the dump or from the current schema, for
+    /// `--verify-against` and `schema-diff`.
+    #[error("{0}")]
+    Mismatch(String),
+}
+
+impl DiscogsLoadError {
+    /// Attach the record being parsed to an invalid value.
+    pub fn in_record(self, entity: &'static str, id: i64) -> Self {
+        match self {
+            DiscogsLoadError::Value(message) => DiscogsLoadError::Parse {
+                entity,
+                id,
+                message,
+            },
+            e => e,
+        }
+    }
+
+    /// Whether the error is about the content of a record, which
+    /// `--on-error skip` leaves out.
+    pub fn in_content(&self) -> bool {
+        self.exit_code() == 5
+    }
+
+    /// The exit status of the CLI for this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            DiscogsLoadError::Mismatch(_) => 2,
+            DiscogsLoadError::Config(_) => 3,
+            DiscogsLoadError::Io(_) | DiscogsLoadError::Parquet(_) => 4,
+            DiscogsLoadError::Xml(_)
+            | DiscogsLoadError::Value(_)
+            | DiscogsLoadError::Parse { .. } => 5,
+            DiscogsLoadError::Db(_)
+            | DiscogsLoadError::Pool(_)
+            | DiscogsLoadError::Sqlite(_)
+            | DiscogsLoadError::Encode { .. } => 6,
+        }
+    }
+}
+
+/// The server's message for errors reported by Postgres, whose `Display` is
+/// only "db error".
+fn db_message(e: &postgres::Error) -> String {
+    match e.as_db_error() {
+        Some(db_error) => db_error.to_string(),
+        None => e.to_string(),
+    }
+}
+
+impl From<ParseIntError> for DiscogsLoadError {
+    fn from(e: ParseIntError) -> Self {
+        DiscogsLoadError::Value(e.to_string())
+    }
+}
+
+impl From<Utf8Error> for DiscogsLoadError {
+    fn from(e: Utf8Error) -> Self {
+        DiscogsLoadError::Value(e.to_string())
+    }
+}
+
+impl From<Infallible> for DiscogsLoadError {
+    fn from(e: Infallible) -> Self {
+        match e {}
+    }
+}
@@ -0,0 +1,44 @@
+use log::info;
+use std::{
+    io::{self, Read},
+    thread,
+    time::{Duration, Instant},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Reads a file that is still being written, like `tail -f`: at the end of
+/// the file it waits for more data until `timeout` passes without any.
+pub struct FollowReader<R> {
+    inner: R,
+    timeout: Option<Duration>,
+}
+
+impl<R: Read> FollowReader<R> {
+    /// With no `timeout` the end of the file is the end of the input.
+    pub fn new(inner: R, timeout: Option<Duration>) -> Self {
+        FollowReader { inner, timeout }
+    }
+}
+
+impl<R: Read> Read for FollowReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return self.inner.read(buf),
+        };
+        let waiting_since = Instant::now();
+        let mut logged = false;
+        loop {
+            let read = self.inner.read(buf)?;
+            if read > 0 || buf.is_empty() || waiting_since.elapsed() >= timeout {
+                return Ok(read);
+            }
+            if !logged {
+                info!("Waiting for the file to grow");
+                logged = true;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
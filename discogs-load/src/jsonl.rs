@@ -0,0 +1,146 @@
+use serde::Serialize;
+use serde_json::Number;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::artist::Artist;
+use crate::db::{DbOpt, SqlSerialization, TableColumns, Value};
+use crate::error::Result;
+use crate::label::Label;
+use crate::master::Master;
+use crate::parser::Record;
+use crate::release::Release;
+use crate::sink::{Sink, TableWriter};
+
+/// Writes the records as newline-delimited JSON to a directory, for
+/// `--output-format jsonl`.
+///
+/// Every record is one object in the file of its entity, `release.jsonl` and
+/// so on, holding the rows of its child tables like the labels and tracks of
+/// a release. Flat, every table gets a file like the CSV files, with an
+/// object per row whose keys are the columns of the table.
+pub struct JsonlSink {
+    dir: PathBuf,
+    flat: bool,
+    columns: TableColumns,
+    files: HashMap<&'static str, BufWriter<File>>,
+    rows: BTreeMap<String, u64>,
+}
+
+impl JsonlSink {
+    /// Create the directory if it is missing. The files are created, or
+    /// replaced, with the first batch written to them.
+    pub fn new(dir: &Path, db_opts: &DbOpt, flat: bool) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(JsonlSink {
+            dir: dir.to_path_buf(),
+            flat,
+            columns: TableColumns::new(db_opts),
+            files: HashMap::new(),
+            rows: BTreeMap::new(),
+        })
+    }
+
+    fn file(&mut self, table: &'static str) -> Result<&mut BufWriter<File>> {
+        if !self.files.contains_key(table) {
+            let file = File::create(self.dir.join(format!("{}.jsonl", table)))?;
+            self.files.insert(table, BufWriter::new(file));
+        }
+        Ok(self.files.get_mut(table).unwrap())
+    }
+
+    /// Write the records of a batch with their child rows nested, or the rows
+    /// of their tables when flat.
+    fn write_records<T: Record + Serialize>(
+        &mut self,
+        table: &'static str,
+        batch: &[T],
+    ) -> Result<()> {
+        if self.flat {
+            return T::write_tables(batch, self);
+        }
+        let file = self.file(table)?;
+        for record in batch {
+            serde_json::to_writer(&mut *file, record).map_err(io::Error::from)?;
+            file.write_all(b"\n")?;
+        }
+        *self.rows.entry(table.to_string()).or_insert(0) += batch.len() as u64;
+        Ok(())
+    }
+}
+
+impl TableWriter for JsonlSink {
+    fn write_rows(
+        &mut self,
+        table: &'static str,
+        rows: &mut dyn Iterator<Item = &dyn SqlSerialization>,
+    ) -> Result<()> {
+        let columns = self.columns.columns(table);
+        let mut lines = Vec::new();
+        let mut written = 0;
+        for row in rows {
+            // the keys in the order of the columns
+            lines.push(b'{');
+            for (i, ((column, _), value)) in columns
+                .iter()
+                .zip(self.columns.values(table, row)?)
+                .enumerate()
+            {
+                if i > 0 {
+                    lines.push(b',');
+                }
+                serde_json::to_writer(&mut lines, column).map_err(io::Error::from)?;
+                lines.push(b':');
+                serde_json::to_writer(&mut lines, &json_value(value)).map_err(io::Error::from)?;
+            }
+            lines.extend_from_slice(b"}\n");
+            written += 1;
+        }
+        self.file(table)?.write_all(&lines)?;
+        *self.rows.entry(table.to_string()).or_insert(0) += written;
+        Ok(())
+    }
+}
+
+impl Sink for JsonlSink {
+    fn write_artists(&mut self, batch: &[Artist]) -> Result<()> {
+        self.write_records("artist", batch)
+    }
+
+    fn write_labels(&mut self, batch: &[Label]) -> Result<()> {
+        self.write_records("label", batch)
+    }
+
+    fn write_masters(&mut self, batch: &[Master]) -> Result<()> {
+        self.write_records("master", batch)
+    }
+
+    fn write_releases(&mut self, batch: &[Release]) -> Result<()> {
+        self.write_records("release", batch)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for file in self.files.values_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    fn row_counts(&self) -> BTreeMap<String, u64> {
+        self.rows.clone()
+    }
+}
+
+fn json_value(value: Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Int(n) => serde_json::Value::Number(Number::from(n)),
+        Value::Bool(b) => serde_json::Value::Bool(b),
+        Value::Text(text) => serde_json::Value::String(text),
+        Value::TextArray(elements) => serde_json::Value::from(elements),
+    }
+}
@@ -1,50 +1,73 @@
-use indicatif::ProgressBar;
 use postgres::types::ToSql;
 use quick_xml::events::Event;
-use std::{collections::HashMap, error::Error, str};
+use serde::Serialize;
+use std::{mem, rc::Rc, str};
 
-use crate::db::{write_labels, DbOpt, SqlSerialization};
-use crate::parser::Parser;
+use crate::db::SqlSerialization;
+use crate::error::Result;
+use crate::parser::{Coverage, DebugSampler, Entity, ParserOpt, Record, RecordParser, TextDecoder};
+use crate::sink::{rows, Sink, TableWriter};
+use crate::transform::{apply_all, dedup_arrays, Transform, Transformable};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct Label {
-    pub id: i32,
-    pub name: String,
-    pub contactinfo: String,
-    pub profile: String,
-    pub parent_label: String,
+    pub id: i64,
+    pub name: Option<String>,
+    pub contactinfo: Option<String>,
+    pub profile: Option<String>,
+    pub parent_label: Option<String>,
     pub sublabels: Vec<String>,
     pub urls: Vec<String>,
-    pub data_quality: String,
+    pub data_quality: Option<String>,
+    pub source_offset: Option<i64>,
 }
 
 impl SqlSerialization for Label {
-    fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
-        let row: Vec<&'_ (dyn ToSql + Sync)> = vec![
-            &self.id,
-            &self.name,
-            &self.contactinfo,
-            &self.profile,
-            &self.parent_label,
-            &self.sublabels,
-            &self.urls,
-            &self.data_quality,
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("id", &self.id),
+            ("name", &self.name),
+            ("contactinfo", &self.contactinfo),
+            ("profile", &self.profile),
+            ("parent_label", &self.parent_label),
+            ("sublabels", &self.sublabels),
+            ("urls", &self.urls),
+            ("data_quality", &self.data_quality),
+            ("source_offset", &self.source_offset),
         ];
         row
     }
 }
 
+impl Transformable for Label {
+    fn text_field(&mut self, name: &str) -> Option<&mut String> {
+        match name {
+            "name" => self.name.as_mut(),
+            "contactinfo" => self.contactinfo.as_mut(),
+            "profile" => self.profile.as_mut(),
+            "parent_label" => self.parent_label.as_mut(),
+            "data_quality" => self.data_quality.as_mut(),
+            _ => None,
+        }
+    }
+
+    fn text_arrays(&mut self) -> Vec<&mut Vec<String>> {
+        vec![&mut self.sublabels, &mut self.urls]
+    }
+}
+
 impl Label {
     pub fn new() -> Self {
         Label {
             id: 0,
-            name: String::new(),
-            contactinfo: String::new(),
-            profile: String::new(),
-            parent_label: String::new(),
+            name: None,
+            contactinfo: None,
+            profile: None,
+            parent_label: None,
             sublabels: Vec::new(),
             urls: Vec::new(),
-            data_quality: String::new(),
+            data_quality: None,
+            source_offset: None,
         }
     }
 }
@@ -64,47 +87,88 @@ enum ParserState {
     DataQuality,
 }
 
-pub struct LabelsParser<'a> {
+impl Record for Label {
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn write(sink: &mut dyn Sink, batch: Vec<Self>) -> Result<()> {
+        sink.take_labels(batch)
+    }
+
+    fn write_tables(batch: &[Self], tables: &mut dyn TableWriter) -> Result<()> {
+        tables.write_rows("label", &mut rows(batch))
+    }
+}
+
+pub struct LabelsParser {
     state: ParserState,
-    labels: HashMap<i32, Label>,
     current_label: Label,
-    pb: ProgressBar,
-    db_opts: &'a DbOpt,
+    records: u64,
+    sampler: DebugSampler,
+    transforms: Vec<Transform>,
+    coverage: Coverage,
+    with_source_offset: bool,
+    dedup_arrays: bool,
+    text: Rc<TextDecoder>,
 }
 
-impl<'a> LabelsParser<'a> {
-    pub fn new(db_opts: &'a DbOpt) -> Self {
+impl RecordParser for LabelsParser {
+    type Record = Label;
+    const ENTITY: Entity = Entity::Label;
+
+    fn new(parser_opts: &ParserOpt, text: Rc<TextDecoder>) -> Self {
         LabelsParser {
             state: ParserState::Label,
-            labels: HashMap::new(),
             current_label: Label::new(),
-            pb: ProgressBar::new(1821993),
-            db_opts,
+            records: 0,
+            sampler: DebugSampler::new(parser_opts.debug_sample),
+            transforms: parser_opts.transforms.clone(),
+            coverage: Coverage::new(parser_opts),
+            with_source_offset: parser_opts.with_source_offset,
+            dedup_arrays: parser_opts.dedup_arrays,
+            text,
         }
     }
-}
 
-impl<'a> Parser<'a> for LabelsParser<'a> {
-    fn new(&self, db_opts: &'a DbOpt) -> Self {
-        LabelsParser {
-            state: ParserState::Label,
-            labels: HashMap::new(),
-            current_label: Label::new(),
-            pb: ProgressBar::new(1821993),
-            db_opts,
-        }
+    fn records(&self) -> u64 {
+        self.records
     }
-    fn process(&mut self, ev: Event) -> Result<(), Box<dyn Error>> {
+
+    fn coverage(&self) -> &Coverage {
+        &self.coverage
+    }
+
+    fn discard(&mut self) {
+        self.records += 1;
+        self.state = ParserState::Label;
+    }
+
+    fn current_id(&self) -> i64 {
+        self.current_label.id
+    }
+
+    fn process(&mut self, ev: Event) -> Result<Option<Label>> {
+        self.read(ev)
+            .map_err(|e| e.in_record("label", self.current_label.id))
+    }
+}
+
+impl LabelsParser {
+    fn read(&mut self, ev: Event) -> Result<Option<Label>> {
+        let mut ended = None;
         self.state = match self.state {
-            ParserState::Label => {
-                match ev {
-                    Event::Start(e) if e.local_name() == b"label" => {
-                        self.current_label.sublabels = Vec::new();
-                        self.current_label.urls = Vec::new();
-                        ParserState::Label
+            ParserState::Label => match ev {
+                Event::Start(e) if e.local_name() == b"label" => {
+                    self.current_label = Label::new();
+                    if self.with_source_offset {
+                        self.current_label.source_offset = Some(self.text.event_start() as i64);
                     }
+                    ParserState::Label
+                }
 
-                    Event::Start(e) => match e.local_name() {
+                Event::Start(e) => {
+                    let state = match e.local_name() {
                         b"name" => ParserState::Name,
                         b"id" => ParserState::Id,
                         b"contactinfo" => ParserState::Contactinfo,
@@ -114,34 +178,41 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
                         b"urls" => ParserState::Urls,
                         b"data_quality" => ParserState::DataQuality,
                         _ => ParserState::Label,
-                    },
-
-                    Event::End(e) if e.local_name() == b"label" => {
-                        self.labels
-                            .entry(self.current_label.id)
-                            .or_insert(self.current_label.clone());
-                        if self.labels.len() >= self.db_opts.batch_size {
-                            // use drain? https://doc.rust-lang.org/std/collections/struct.HashMap.html#examples-13
-                            write_labels(self.db_opts, &self.labels)?;
-                            self.labels = HashMap::new();
-                        }
-                        self.pb.inc(1);
-                        ParserState::Label
-                    }
+                    };
+                    self.coverage
+                        .saw(e.local_name(), !matches!(state, ParserState::Label));
+                    state
+                }
 
-                    Event::End(e) if e.local_name() == b"labels" => {
-                        // write to db remainder of labels
-                        write_labels(self.db_opts, &self.labels)?;
-                        ParserState::Label
-                    }
+                Event::End(e)
+                    if e.local_name() == b"label"
+                        && self.text.skip_record("label", self.current_label.id) =>
+                {
+                    self.discard();
+                    ParserState::Label
+                }
 
-                    _ => ParserState::Label,
+                Event::End(e) if e.local_name() == b"label" => {
+                    apply_all(&self.transforms, &mut self.current_label);
+                    if self.dedup_arrays {
+                        dedup_arrays(&mut self.current_label);
+                    }
+                    self.sampler.sample(&self.current_label);
+                    let id = self.current_label.id;
+                    ended = Some(mem::replace(
+                        &mut self.current_label,
+                        Label { id, ..Label::new() },
+                    ));
+                    self.records += 1;
+                    ParserState::Label
                 }
-            }
+
+                _ => ParserState::Label,
+            },
 
             ParserState::Id => match ev {
                 Event::Text(e) => {
-                    self.current_label.id = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_label.id = str::parse(&self.text.decode(&e)?)?;
                     ParserState::Id
                 }
 
@@ -152,7 +223,7 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
 
             ParserState::Name => match ev {
                 Event::Text(e) => {
-                    self.current_label.name = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_label.name = Some(self.text.decode(&e)?);
                     ParserState::Name
                 }
 
@@ -163,7 +234,7 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
 
             ParserState::Contactinfo => match ev {
                 Event::Text(e) => {
-                    self.current_label.contactinfo = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_label.contactinfo = Some(self.text.decode(&e)?);
                     ParserState::Contactinfo
                 }
 
@@ -174,7 +245,7 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
 
             ParserState::Profile => match ev {
                 Event::Text(e) => {
-                    self.current_label.profile = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_label.profile = Some(self.text.decode(&e)?);
                     ParserState::Profile
                 }
 
@@ -185,7 +256,7 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
 
             ParserState::ParentLabel => match ev {
                 Event::Text(e) => {
-                    self.current_label.parent_label = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_label.parent_label = Some(self.text.decode(&e)?);
                     ParserState::ParentLabel
                 }
 
@@ -206,7 +277,7 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
                 Event::Text(e) => {
                     self.current_label
                         .sublabels
-                        .extend(str::parse(str::from_utf8(&e.unescaped()?)?));
+                        .extend(str::parse(&self.text.decode(&e)?));
                     ParserState::Sublabels
                 }
 
@@ -225,7 +296,7 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
                 Event::Text(e) => {
                     self.current_label
                         .urls
-                        .extend(str::parse(str::from_utf8(&e.unescaped()?)?));
+                        .extend(str::parse(&self.text.decode(&e)?));
                     ParserState::Urls
                 }
 
@@ -234,7 +305,7 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
 
             ParserState::DataQuality => match ev {
                 Event::Text(e) => {
-                    self.current_label.data_quality = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_label.data_quality = Some(self.text.decode(&e)?);
                     ParserState::DataQuality
                 }
 
@@ -244,6 +315,6 @@ impl<'a> Parser<'a> for LabelsParser<'a> {
             },
         };
 
-        Ok(())
+        Ok(ended)
     }
 }
@@ -0,0 +1,30 @@
+//! Parsers of the Discogs monthly data dumps and their loading into Postgres,
+//! used by the `discogs-load` binary.
+//!
+//! The records of a dump can also be read without a database, with
+//! [`ReleaseReader`] and the other readers of the [`reader`] module, or
+//! written to files like the CSV files of [`csv::CsvSink`].
+
+pub mod artist;
+pub mod csv;
+pub mod db;
+pub mod error;
+pub mod jsonl;
+pub mod label;
+pub mod master;
+pub mod parquet;
+pub mod parser;
+mod pipe;
+pub mod reader;
+pub mod release;
+pub mod sink;
+pub mod sqlite;
+pub mod transform;
+mod verify;
+
+pub use artist::Artist;
+pub use error::{DiscogsLoadError, Result};
+pub use label::Label;
+pub use master::Master;
+pub use reader::{ArtistReader, LabelReader, MasterReader, Records, ReleaseReader};
+pub use release::Release;
@@ -1,110 +1,1228 @@
-use anyhow::Result;
-use flate2::read::GzDecoder;
-use log::info;
+use flate2::bufread::GzDecoder;
+use log::{info, warn};
 use quick_xml::{events::Event, Reader};
-use std::{error::Error, fs::File, io::BufReader, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 use structopt::StructOpt;
 
-mod artist;
-mod db;
-mod label;
-mod master;
-mod parser;
-mod release;
+use checkpoint::{Checkpoint, FilePosition};
+use discogs_load::{
+    artist, csv, db, error, jsonl, label, master, parquet, parser, release, sink, sqlite,
+};
+use error::{DiscogsLoadError, Result};
+use parser::{Loader, OnError, RecordParser};
+use sink::{Backend, OutputFormat, Sink, ThreadedSink};
+
+mod checkpoint;
+mod decompress;
+mod follow;
+mod manifest;
+mod progress;
 
 const BUF_SIZE: usize = 4096; // 4kb at once
+/// The file name reading the dump from stdin.
+const STDIN: &str = "-";
+/// The first bytes of a gzip file.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Load order of the dump files found in a directory, referenced entities first.
+const DUMP_TYPES: [&str; 4] = ["artists", "labels", "masters", "releases"];
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "discogs-load")]
 struct Opt {
-    /// Path to one or more discogs monthly data dump files, still compressed
+    /// Path to one or more discogs monthly data dump files, compressed or
+    /// decompressed, or directories holding them. A single `-` reads a dump
+    /// from stdin
     #[structopt(name = "FILE(S)", parse(from_os_str))]
     files: Vec<PathBuf>,
 
+    /// Bytes of decompressed XML read at once
+    #[structopt(long = "read-buffer-size", default_value = "1048576")]
+    read_buffer_size: usize,
+
+    /// Stop at the first record boundary after this many bytes of decompressed
+    /// XML, e.g. 100MB, to load a sample of a dump
+    #[structopt(long = "limit-bytes", parse(try_from_str = parse_bytes))]
+    limit_bytes: Option<usize>,
+
+    /// Stop after the first N records of every file, to load a sample of a
+    /// dump
+    #[structopt(long = "limit")]
+    limit: Option<u64>,
+
+    /// Keep reading a file that is still being written, waiting for more data
+    /// at its end
+    #[structopt(long = "follow")]
+    follow: bool,
+
+    /// Give up on a followed file after it did not grow for this long
+    #[structopt(long = "follow-timeout", default_value = "60s", parse(try_from_str = humantime::parse_duration))]
+    follow_timeout: Duration,
+
+    /// Decompress on a separate thread, overlapping decompression and parsing
+    #[structopt(long = "threaded-decompress")]
+    threaded_decompress: bool,
+
+    /// Write the batches to Postgres on this many threads with a connection
+    /// each, while the next batches are parsed. 0 writes them on the parsing
+    /// thread
+    #[structopt(long = "writer-threads", default_value = "0")]
+    writer_threads: usize,
+
+    /// Show the progress of a file as the records parsed of this many,
+    /// instead of the bytes read of its size, like for a dump read from stdin
+    #[structopt(long = "expected-records")]
+    expected_records: Option<u64>,
+
+    /// Don't show a progress bar, like in the logs of a CI job
+    #[structopt(long = "no-progress")]
+    no_progress: bool,
+
+    /// Parse the dumps without writing anything or connecting to the
+    /// database, and print the rows every table would get
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+
+    /// Load this many files at the same time, with a connection each. Files
+    /// writing to the same tables are still loaded one after the other
+    #[structopt(long = "jobs", default_value = "1")]
+    jobs: usize,
+
+    /// Keep track of how far every file got in this JSON file, and continue
+    /// from there when it exists
+    #[structopt(long = "checkpoint", parse(from_os_str))]
+    checkpoint: Option<PathBuf>,
+
+    /// Compare a sample of the rows in the tables with this dump instead of
+    /// loading anything, parsed with the options it was loaded with
+    #[structopt(long = "verify-against", parse(from_os_str))]
+    verify_against: Option<PathBuf>,
+
+    /// Number of rows per table compared by `--verify-against`
+    #[structopt(long = "verify-sample", default_value = "100")]
+    verify_sample: usize,
+
+    /// Write a JSON manifest describing the processed files to this path
+    #[structopt(long = "manifest-out", parse(from_os_str))]
+    manifest_out: Option<PathBuf>,
+
+    /// The database the records are loaded into, `sqlite` creates the tables
+    /// in the file of `--sqlite-path` instead of connecting to Postgres
+    #[structopt(
+        long = "backend",
+        default_value = "postgres",
+        possible_values = &["postgres", "sqlite"]
+    )]
+    backend: Backend,
+
+    /// The SQLite database file loaded with `--backend sqlite`
+    #[structopt(long = "sqlite-path", default_value = "discogs.db", parse(from_os_str))]
+    sqlite_path: PathBuf,
+
+    /// Where the records go, `csv` writes one file per table to
+    /// `--output-dir` instead of loading them into the database, like
+    /// `parquet` does, `jsonl` one file per entity with a JSON object per
+    /// record
+    #[structopt(
+        long = "output-format",
+        default_value = "postgres",
+        possible_values = &["postgres", "csv", "jsonl", "parquet"]
+    )]
+    output_format: OutputFormat,
+
+    /// Directory of the files written with `--output-format`
+    #[structopt(long = "output-dir", parse(from_os_str))]
+    output_dir: Option<PathBuf>,
+
+    /// Join the elements of array columns like genres with this delimiter in
+    /// CSV files, instead of writing them as Postgres array literals
+    #[structopt(long = "csv-array-delimiter")]
+    csv_array_delimiter: Option<String>,
+
+    /// Write a JSONL file per table with an object per row, instead of
+    /// nesting the rows of the child tables in the objects of their records
+    #[structopt(long = "jsonl-flat")]
+    jsonl_flat: bool,
+
+    // Parser related arguments
+    #[structopt(flatten)]
+    parseropts: parser::ParserOpt,
+
     // DB related arguments
     #[structopt(flatten)]
     dbopts: db::DbOpt,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Compare the existing tables with the columns this version writes,
+    /// without loading anything
+    SchemaDiff,
+    /// Write the DDL of all tables and indexes for the given options to a
+    /// file, without connecting to the database
+    GenMigration {
+        /// Path of the migration script
+        #[structopt(long = "out", parse(from_os_str))]
+        out: PathBuf,
+    },
+    /// Print the DDL of the tables for the given options, without connecting
+    /// to the database
+    Schema {
+        /// Only print the tables of this entity
+        #[structopt(
+            long = "entity",
+            possible_values = &["artist", "label", "master", "release"]
+        )]
+        entity: Option<parser::Entity>,
+    },
 }
 
-fn main() -> Result<()> {
+fn main() {
     let log_env = env_logger::Env::default().default_filter_or("info");
     env_logger::Builder::from_env(log_env).init();
 
     let opt = Opt::from_args();
 
-    if let Err(e) = read_files(&opt) {
-        println!("{:?}", e);
-        std::process::exit(1);
+    let result = match &opt.cmd {
+        Some(Command::SchemaDiff) => schema_diff(&opt),
+        Some(Command::GenMigration { out }) => gen_migration(&opt, out),
+        Some(Command::Schema { entity }) => print_schema(&opt, *entity),
+        None => match &opt.verify_against {
+            Some(dump) => verify(&opt, dump),
+            None => read_files(&opt),
+        },
+    };
+    if let Err(e) = result {
+        println!("{}", e);
+        std::process::exit(e.exit_code());
+    }
+}
+
+fn read_files(opt: &Opt) -> Result<()> {
+    let started = Instant::now();
+    if opt.dbopts.append && opt.dbopts.atomic_swap {
+        return Err(DiscogsLoadError::Config(
+            "--append loads into the existing tables and can't be combined with --atomic-swap"
+                .to_string(),
+        ));
+    }
+    if opt.dbopts.on_conflict != db::OnConflict::Error && !opt.dbopts.append {
+        return Err(DiscogsLoadError::Config(
+            "--on-conflict handles the records already in the tables and needs --append"
+                .to_string(),
+        ));
+    }
+    if opt
+        .files
+        .iter()
+        .filter(|path| *path == Path::new(STDIN))
+        .count()
+        > 1
+    {
+        return Err(DiscogsLoadError::Config(
+            "Only one dump can be read from stdin".to_string(),
+        ));
+    }
+    let mut files = Vec::new();
+    for path in &opt.files {
+        if path.is_dir() {
+            files.extend(dump_files(path)?);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    if opt.backend != Backend::Postgres && opt.output_format != OutputFormat::Postgres {
+        return Err(DiscogsLoadError::Config(
+            "--output-format writes files instead of a database and can't be combined with --backend"
+                .to_string(),
+        ));
+    }
+    if opt.dry_run {
+        return dry_run(opt, &files);
+    }
+    match (opt.backend, opt.output_format) {
+        (Backend::Sqlite, _) => {
+            let unsupported = postgres_only_options(opt);
+            if !unsupported.is_empty() {
+                return Err(DiscogsLoadError::Config(format!(
+                    "--backend sqlite doesn't support the Postgres options {}",
+                    unsupported.join(", ")
+                )));
+            }
+            let mut sink = sqlite::SqliteSink::open(&opt.sqlite_path, &opt.dbopts)?;
+            return export_files(opt, &files, &mut sink);
+        }
+        (Backend::Postgres, OutputFormat::Postgres) => {}
+        (Backend::Postgres, OutputFormat::Csv) => {
+            let mut sink = csv::CsvSink::new(
+                output_dir(opt)?,
+                &opt.dbopts,
+                opt.csv_array_delimiter.clone(),
+            )?;
+            return export_files(opt, &files, &mut sink);
+        }
+        (Backend::Postgres, OutputFormat::Jsonl) => {
+            let mut sink = jsonl::JsonlSink::new(output_dir(opt)?, &opt.dbopts, opt.jsonl_flat)?;
+            return export_files(opt, &files, &mut sink);
+        }
+        (Backend::Postgres, OutputFormat::Parquet) => {
+            let mut sink = parquet::ParquetSink::new(output_dir(opt)?, &opt.dbopts)?;
+            return export_files(opt, &files, &mut sink);
+        }
+    }
+    if (opt.writer_threads > 0 || opt.jobs > 1) && opt.checkpoint.is_some() {
+        return Err(DiscogsLoadError::Config(
+            "--checkpoint records the batches written one after the other and can't be combined with --writer-threads or --jobs"
+                .to_string(),
+        ));
+    }
+    if opt.writer_threads > 0 && opt.dbopts.auto_batch {
+        return Err(DiscogsLoadError::Config(
+            "--auto-batch times the writes on the parsing thread and can't be combined with --writer-threads"
+                .to_string(),
+        ));
+    }
+    if opt.jobs == 0 {
+        return Err(DiscogsLoadError::Config(
+            "--jobs needs at least one job".to_string(),
+        ));
+    }
+    // a connection per job, or per writer thread of a job
+    let connections = opt.jobs * opt.writer_threads.max(1);
+    if connections > opt.dbopts.pool_size.get() as usize {
+        return Err(DiscogsLoadError::Config(format!(
+            "--jobs {} and --writer-threads {} need {} connections, raise --pool-size to at least as many",
+            opt.jobs, opt.writer_threads, connections
+        )));
+    }
+    let pool = db::pool(&opt.dbopts)?;
+    let mut manifest = manifest::Manifest::default();
+    let mut checkpoint = match &opt.checkpoint {
+        Some(path) => Some(Checkpoint::load(path)?),
+        None => None,
+    };
+
+    // one pool per set of tables, several with --schema-prefix-from-filename
+    let mut loaded: Vec<db::Pool> = Vec::new();
+    if opt.jobs > 1 {
+        let result = load_jobs(opt, &pool, &files, &mut manifest, &mut loaded);
+        if result.is_err() {
+            write_manifest(opt, &manifest)?;
+            return result;
+        }
+    } else {
+        for file in &files {
+            let mut entry = manifest::FileEntry::start(file);
+            let rows_before = pool.row_counts();
+            let file_pool = file_pool(opt, &pool, file, &mut loaded)?;
+            let result = read_file(opt, &file_pool, file, &mut entry, &mut checkpoint);
+            entry.finish(&result, &rows_before, &pool.row_counts());
+            manifest.files.push(entry);
+            if result.is_err() {
+                write_manifest(opt, &manifest)?;
+                return result;
+            }
+        }
+    }
+
+    log_summary(&pool.row_counts(), started);
+
+    if loaded.is_empty() {
+        loaded.push(pool);
     }
+    for pool in &loaded {
+        if opt.dbopts.create_indexes {
+            db::indexes(pool, "sql/indexes.sql")?;
+        }
+
+        if let Some(foreign_keys) = opt.dbopts.foreign_keys {
+            db::constraints(pool, "sql/constraints.sql", foreign_keys)?;
+        }
+
+        if opt.dbopts.validate_fk {
+            for (relationship, orphans) in db::orphaned_references(pool)? {
+                info!("{}: {} orphaned references", relationship, orphans);
+            }
+        }
+
+        if opt.dbopts.report_orphans {
+            report_orphans(pool)?;
+        }
+    }
+
+    write_manifest(opt, &manifest)?;
     Ok(())
 }
 
-fn read_files(opt: &Opt) -> Result<(), Box<dyn Error>> {
-    for file in &opt.files {
-        let gzfile = File::open(file.to_str().unwrap())?;
-        let xmlfile = GzDecoder::new(gzfile);
-        let xmlfile = BufReader::new(xmlfile);
-        let mut xmlfile = Reader::from_reader(xmlfile);
-        let mut buf = Vec::with_capacity(BUF_SIZE);
+/// The pool of the tables a file is loaded into, adding it to the pools of
+/// the loaded tables.
+fn file_pool(
+    opt: &Opt,
+    pool: &db::Pool,
+    file: &Path,
+    loaded: &mut Vec<db::Pool>,
+) -> Result<db::Pool> {
+    let file_pool = if opt.dbopts.schema_prefix_from_filename {
+        pool.with_table_suffix(&format!("_{}", dump_date(file)?))
+    } else {
+        pool.clone()
+    };
+    if !loaded
+        .iter()
+        .any(|loaded| loaded.table_suffix() == file_pool.table_suffix())
+    {
+        loaded.push(file_pool.clone());
+    }
+    Ok(file_pool)
+}
 
-        // Parse fileinput on type (label/release/artist)
-        let mut parser: Box<dyn parser::Parser> = loop {
-            if let Event::Start(ref e) = xmlfile.read_event(&mut buf)? {
-                match e.name() {
-                    b"labels" => {
-                        db::init(&opt.dbopts, "sql/tables/label.sql")?;
-                        break Box::new(parser::Parser::new(
-                            &label::LabelsParser::new(&opt.dbopts),
-                            &opt.dbopts,
-                        ));
-                    }
-                    b"releases" => {
-                        db::init(&opt.dbopts, "sql/tables/release.sql")?;
-                        break Box::new(parser::Parser::new(
-                            &release::ReleasesParser::new(&opt.dbopts),
-                            &opt.dbopts,
-                        ));
-                    }
-                    b"artists" => {
-                        db::init(&opt.dbopts, "sql/tables/artist.sql")?;
-                        break Box::new(parser::Parser::new(
-                            &artist::ArtistsParser::new(&opt.dbopts),
-                            &opt.dbopts,
-                        ));
+/// A file of a `--jobs` load, by its position among the files.
+type JobFile<'f> = (usize, manifest::FileEntry, db::Pool, PreparedFile<'f>);
+
+/// Load the files on `--jobs` threads. The tables of all files are created
+/// first, one file after the other, then every job loads the files of a set
+/// of tables in turn, so no two files are written to the same table at once.
+/// Those replace the rows of the file before unless appending, like they do
+/// without `--jobs`.
+/// Once a job failed the others finish their files without starting new ones.
+fn load_jobs(
+    opt: &Opt,
+    pool: &db::Pool,
+    files: &[PathBuf],
+    manifest: &mut manifest::Manifest,
+    loaded: &mut Vec<db::Pool>,
+) -> Result<()> {
+    let mut entries: Vec<Option<manifest::FileEntry>> = Vec::new();
+    let mut groups: Vec<Vec<JobFile>> = Vec::new();
+    for (i, file) in files.iter().enumerate() {
+        let mut entry = manifest::FileEntry::start(file);
+        let prepared = file_pool(opt, pool, file, loaded).and_then(|file_pool| {
+            let prepared = prepare_file(opt, &file_pool, file, &mut entry, &None)?;
+            Ok((file_pool, prepared))
+        });
+        match prepared {
+            Ok((file_pool, Some(prepared))) => {
+                entries.push(None);
+                let same_tables = groups.iter_mut().find(|group| {
+                    let (_, _, _, first) = &group[0];
+                    first.dump.entity == prepared.dump.entity
+                        && first.pool.table_suffix() == prepared.pool.table_suffix()
+                });
+                match same_tables {
+                    Some(group) => group.push((i, entry, file_pool, prepared)),
+                    None => groups.push(vec![(i, entry, file_pool, prepared)]),
+                }
+            }
+            Ok((_, None)) => {
+                entry.finish(&Result::<()>::Ok(()), &BTreeMap::new(), &BTreeMap::new());
+                entries.push(Some(entry));
+            }
+            Err(e) => {
+                let result = Err(e);
+                entry.finish(&result, &BTreeMap::new(), &BTreeMap::new());
+                manifest.files.extend(entries.into_iter().flatten());
+                manifest.files.push(entry);
+                return result;
+            }
+        }
+    }
+
+    let groups = Mutex::new(groups.into_iter());
+    let entries = Mutex::new(entries);
+    let failed = AtomicBool::new(false);
+    let results: Vec<Result<()>> = thread::scope(|scope| {
+        let jobs: Vec<_> = (0..opt.jobs)
+            .map(|_| {
+                scope.spawn(|| loop {
+                    if failed.load(Ordering::Relaxed) {
+                        return Ok(());
                     }
-                    b"masters" => {
-                        db::init(&opt.dbopts, "sql/tables/master.sql")?;
-                        break Box::new(parser::Parser::new(
-                            &master::MastersParser::new(&opt.dbopts),
-                            &opt.dbopts,
-                        ));
+                    let group = match groups.lock().unwrap().next() {
+                        Some(group) => group,
+                        None => return Ok(()),
+                    };
+                    for (n, (i, mut entry, file_pool, prepared)) in group.into_iter().enumerate() {
+                        // the rows of the other jobs' tables are left out
+                        let tables: Vec<String> = prepared
+                            .dump
+                            .entity
+                            .tables()
+                            .iter()
+                            .map(|table| format!("{}{}", table, prepared.pool.table_suffix()))
+                            .collect();
+                        let rows_before = pool.row_counts();
+                        // a file replaces the rows of the one before, like
+                        // when loading the files one after the other
+                        let result = if n > 0 {
+                            prepared.reset_tables()
+                        } else {
+                            Ok(())
+                        };
+                        let result = result.and_then(|_| {
+                            load_file(opt, &file_pool, prepared, &mut entry, &mut None)
+                        });
+                        let mut rows_after = pool.row_counts();
+                        rows_after.retain(|table, _| tables.contains(table));
+                        entry.finish(&result, &rows_before, &rows_after);
+                        entries.lock().unwrap()[i] = Some(entry);
+                        if result.is_err() {
+                            failed.store(true, Ordering::Relaxed);
+                            return result;
+                        }
                     }
-                    _ => (),
-                };
-                buf.clear();
-            };
-            buf.clear();
+                })
+            })
+            .collect();
+        jobs.into_iter()
+            .map(|job| job.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    });
+    manifest
+        .files
+        .extend(entries.into_inner().unwrap().into_iter().flatten());
+    results.into_iter().collect()
+}
+
+/// Log how many releases reference a missing master, with a few examples.
+fn report_orphans(pool: &db::Pool) -> Result<()> {
+    match db::orphaned_masters(pool, 10)? {
+        Some(orphans) => {
+            info!(
+                "{} releases reference a master that isn't loaded",
+                orphans.count
+            );
+            for (release, master) in orphans.examples {
+                info!("release {} -> master {}", release, master);
+            }
+        }
+        None => warn!("The release and master tables aren't both loaded, no orphans to report"),
+    }
+    Ok(())
+}
+
+/// Write the records of the dumps to the sink of `--output-format` or
+/// `--backend sqlite` instead of Postgres.
+fn export_files(opt: &Opt, files: &[PathBuf], sink: &mut dyn Sink) -> Result<()> {
+    let started = Instant::now();
+    if opt.checkpoint.is_some() {
+        return Err(DiscogsLoadError::Config(
+            "--checkpoint only resumes loads into Postgres, not with --output-format, --backend or --dry-run"
+                .to_string(),
+        ));
+    }
+    let mut manifest = manifest::Manifest::default();
+    for file in files {
+        let mut entry = manifest::FileEntry::start(file);
+        let rows_before = sink.row_counts();
+        let result = export_file(opt, sink, file, &mut entry);
+        entry.finish(&result, &rows_before, &sink.row_counts());
+        manifest.files.push(entry);
+        if result.is_err() {
+            write_manifest(opt, &manifest)?;
+            return result;
+        }
+    }
+    sink.finish()?;
+    if !opt.dry_run {
+        log_summary(&sink.row_counts(), started);
+    }
+    write_manifest(opt, &manifest)
+}
+
+/// Log the rows written to every table, and how fast they were written.
+fn log_summary(rows: &BTreeMap<String, u64>, started: Instant) {
+    let elapsed = started.elapsed();
+    let total: u64 = rows.values().sum();
+    let tables: Vec<String> = rows
+        .iter()
+        .map(|(table, rows)| format!("{}: {} rows", table, thousands(*rows)))
+        .collect();
+    if !tables.is_empty() {
+        info!("{}", tables.join("; "));
+    }
+    info!(
+        "Wrote {} rows in {:.1?}, {} rows/s",
+        thousands(total),
+        elapsed,
+        thousands((total as f64 / elapsed.as_secs_f64().max(0.001)) as u64)
+    );
+}
+
+/// A number with its thousands separated, like `14,979,211`.
+fn thousands(n: u64) -> String {
+    let digits = n.to_string();
+    let groups: Vec<&str> = digits
+        .as_bytes()
+        .rchunks(3)
+        .rev()
+        .map(|group| std::str::from_utf8(group).unwrap())
+        .collect();
+    groups.join(",")
+}
+
+/// Parse the dumps like a load and print the rows of every table, which
+/// also checks that they are well-formed.
+fn dry_run(opt: &Opt, files: &[PathBuf]) -> Result<()> {
+    let mut sink = sink::CountingSink::default();
+    export_files(opt, files, &mut sink)?;
+    let rows = sink.row_counts();
+    for (table, rows) in &rows {
+        println!("{}: {} rows", table, rows);
+    }
+    println!(
+        "{} rows in total, nothing was written.",
+        rows.values().sum::<u64>()
+    );
+    Ok(())
+}
+
+/// The options given that only the Postgres backend supports.
+fn postgres_only_options(opt: &Opt) -> Vec<&'static str> {
+    let db_opts = &opt.dbopts;
+    [
+        ("--create-indexes", db_opts.create_indexes),
+        (
+            "--on-conflict",
+            db_opts.on_conflict != db::OnConflict::Error,
+        ),
+        ("--foreign-keys", db_opts.foreign_keys.is_some()),
+        ("--validate-fk", db_opts.validate_fk),
+        ("--report-orphans", db_opts.report_orphans),
+        ("--atomic-swap", db_opts.atomic_swap),
+        ("--copy-to-pipe", db_opts.copy_to_pipe.is_some()),
+    ]
+    .iter()
+    .filter(|(_, given)| *given)
+    .map(|(option, _)| *option)
+    .collect()
+}
+
+fn output_dir(opt: &Opt) -> Result<&Path> {
+    opt.output_dir.as_deref().ok_or_else(|| {
+        DiscogsLoadError::Config("--output-format needs an --output-dir to write to".to_string())
+    })
+}
+
+/// Ask before the tables of an entity holding rows are dropped, which is only
+/// possible in a terminal.
+fn confirm_replace(entity: parser::Entity) -> Result<()> {
+    let question = format!("The {} table already has rows.", entity.name());
+    if !io::stdin().is_terminal() {
+        return Err(DiscogsLoadError::Config(format!(
+            "{} Pass --yes to replace it when not running in a terminal",
+            question
+        )));
+    }
+    eprint!("{} Drop and load it again? [y/N] ", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    match answer.trim() {
+        "y" | "Y" | "yes" => Ok(()),
+        _ => Err(DiscogsLoadError::Config(format!(
+            "Aborted, the {} table was left as it is",
+            entity.name()
+        ))),
+    }
+}
+
+/// Find the `discogs_<date>_<type>.xml.gz` files in a directory, or the
+/// decompressed `.xml` ones, ordered so that artists and labels load before
+/// the masters and releases using them.
+fn dump_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for dir_entry in std::fs::read_dir(dir)? {
+        let path = dir_entry?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => continue,
         };
+        let dump_type = name
+            .strip_prefix("discogs_")
+            .and_then(|name| {
+                name.strip_suffix(".xml.gz")
+                    .or_else(|| name.strip_suffix(".xml"))
+            })
+            .and_then(|name| name.split('_').nth(1))
+            .and_then(|dump_type| DUMP_TYPES.iter().position(|t| *t == dump_type));
+        if let Some(order) = dump_type {
+            files.push((order, path));
+        }
+    }
+    files.sort();
+    info!("Found {} dump files in {:?}", files.len(), dir);
+    Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+/// The `YYYYMMDD` date in the name of a dump file like
+/// `discogs_20231001_releases.xml.gz`.
+fn dump_date(file: &Path) -> Result<&str> {
+    file.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| {
+            name.split(['_', '.'])
+                .find(|token| token.len() == 8 && token.bytes().all(|b| b.is_ascii_digit()))
+        })
+        .ok_or_else(|| {
+            DiscogsLoadError::Config(format!(
+                "No YYYYMMDD date in the file name of {:?} for --schema-prefix-from-filename",
+                file
+            ))
+        })
+}
+
+/// The XML reader of a dump, decompressed when it is gzipped.
+type DumpReader = Reader<BufReader<Box<dyn Read + Send>>>;
+
+/// Open a dump for parsing, with the counter of the bytes read from the file.
+fn open_dump(file: &Path, opt: &Opt) -> Result<(DumpReader, Arc<AtomicU64>)> {
+    let input: Box<dyn Read + Send> = if file == Path::new(STDIN) {
+        Box::new(io::stdin())
+    } else {
+        let timeout = Some(opt.follow_timeout).filter(|_| opt.follow);
+        Box::new(follow::FollowReader::new(File::open(file)?, timeout))
+    };
+    let (input, read) = progress::CountingReader::new(input);
+    let mut input = BufReader::new(input);
+    // dumps that were decompressed already are read as they are
+    let xmlfile: Box<dyn Read + Send> = if input.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        Box::new(GzDecoder::new(input))
+    } else {
+        Box::new(input)
+    };
+    let xmlfile: Box<dyn Read + Send> = if opt.threaded_decompress {
+        Box::new(decompress::ThreadedReader::new(xmlfile))
+    } else {
+        Box::new(xmlfile)
+    };
+    let xmlfile = BufReader::with_capacity(opt.read_buffer_size, xmlfile);
+    Ok((Reader::from_reader(xmlfile), read))
+}
+
+/// A dump opened for parsing, with the entity of its records.
+struct Dump<'f> {
+    file: &'f Path,
+    xmlfile: DumpReader,
+    buf: Vec<u8>,
+    entity: parser::Entity,
+    /// The first record of a fragment without a dump root, read while
+    /// looking for the root, and where it started.
+    first_record: Option<(Event<'static>, usize)>,
+    /// Bytes read from the file so far, and its length when it is known.
+    read: Arc<AtomicU64>,
+    len: Option<u64>,
+}
 
-        // Parse and insert file
-        let gzfile = File::open(file.to_str().unwrap())?;
-        let xmlfile = GzDecoder::new(gzfile);
-        let xmlfile = BufReader::new(xmlfile);
-        let mut xmlfile = Reader::from_reader(xmlfile);
+impl<'f> Dump<'f> {
+    /// Open a dump and detect its entity from the root element, the records
+    /// are parsed from there on with the same reader.
+    fn open(file: &'f Path, opt: &Opt) -> Result<Self> {
+        let (mut xmlfile, read) = open_dump(file, opt)?;
+        // a file that is still being written grows past its current length
+        let len = if file == Path::new(STDIN) || opt.follow {
+            None
+        } else {
+            Some(fs::metadata(file)?.len())
+        };
         let mut buf = Vec::with_capacity(BUF_SIZE);
-        info!("Parsing and inserting: {:?}", file.file_name().unwrap());
-        loop {
+        let forced = opt.parseropts.force_entity;
+        let (entity, first_record) = loop {
+            let event_start = xmlfile.buffer_position();
             match xmlfile.read_event(&mut buf)? {
-                Event::Eof => break,
-                ev => parser.process(ev)?,
+                Event::Start(e) => match (parser::Entity::from_root(e.name()), forced) {
+                    (Some(found), Some(forced)) if found != forced => {
+                        return Err(DiscogsLoadError::Config(format!(
+                            "{:?} is a {} dump, but --force-entity is {}",
+                            file,
+                            found.name(),
+                            forced.name()
+                        )))
+                    }
+                    (Some(entity), _) => break (entity, None),
+                    (None, Some(entity)) => {
+                        break (entity, Some((Event::Start(e.into_owned()), event_start)))
+                    }
+                    (None, None) => {}
+                },
+                Event::Eof => {
+                    return Err(DiscogsLoadError::Config(format!(
+                        "No dump root element found in {:?}, use --force-entity to parse it anyway",
+                        file
+                    )))
+                }
+                _ => {}
             };
             buf.clear();
+        };
+        Ok(Dump {
+            file,
+            xmlfile,
+            buf,
+            entity,
+            first_record,
+            read,
+            len,
+        })
+    }
+}
+
+fn read_file(
+    opt: &Opt,
+    pool: &db::Pool,
+    file: &Path,
+    entry: &mut manifest::FileEntry,
+    checkpoint: &mut Option<Checkpoint>,
+) -> Result<()> {
+    match prepare_file(opt, pool, file, entry, checkpoint)? {
+        Some(prepared) => load_file(opt, pool, prepared, entry, checkpoint),
+        None => Ok(()),
+    }
+}
+
+/// A dump about to be loaded, whose tables were created or emptied for it.
+struct PreparedFile<'f> {
+    dump: Dump<'f>,
+    /// The pool of the tables written to, the `<table>_new` ones with
+    /// `--atomic-swap`.
+    pool: db::Pool,
+    resume_offset: usize,
+    create_tables: bool,
+    /// Whether the rows already in the tables are dropped.
+    empty_tables: bool,
+}
+
+/// Open a dump and get its tables ready, or return `None` when the file is
+/// skipped.
+fn prepare_file<'f>(
+    opt: &Opt,
+    pool: &db::Pool,
+    file: &'f Path,
+    entry: &mut manifest::FileEntry,
+    checkpoint: &Option<Checkpoint>,
+) -> Result<Option<PreparedFile<'f>>> {
+    let dump = Dump::open(file, opt)?;
+    let entity = dump.entity;
+    entry.entity = Some(entity.name().to_string());
+    let resume = checkpoint
+        .as_ref()
+        .and_then(|checkpoint| checkpoint.files.get(&file.display().to_string()))
+        .cloned();
+    match &resume {
+        Some(position) if position.complete => {
+            info!(
+                "Skipping {:?}, the checkpoint has it loaded",
+                file.file_name().unwrap()
+            );
+            return Ok(None);
+        }
+        Some(position) => info!(
+            "Resuming {:?} after {} {} at byte {}",
+            file.file_name().unwrap(),
+            entity.name(),
+            position.last_id,
+            position.offset
+        ),
+        None => {}
+    }
+    let resume_offset = resume.as_ref().map_or(0, |position| position.offset);
+    // a resumed file goes on with the tables the checkpointed batches went to
+    let resuming = pool.verify_report().is_some() || resume.is_some();
+    let keep_tables = opt.dbopts.append || opt.dbopts.truncate || resuming;
+    // the rows already in the tables are dropped, unless appending to them
+    let replace = !resuming && (opt.dbopts.truncate || !opt.dbopts.append);
+
+    if opt.dbopts.skip_populated && replace && db::populated(pool, entity)? {
+        info!(
+            "Skipping {:?}, the {} table already has rows",
+            file.file_name().unwrap(),
+            entity.name()
+        );
+        return Ok(None);
+    }
+    // loading replaces the live tables, also with --atomic-swap
+    if replace && !opt.dbopts.yes && db::populated(pool, entity)? {
+        confirm_replace(entity)?;
+    }
+
+    let pool = if opt.dbopts.atomic_swap {
+        pool.with_table_suffix("_new")
+    } else {
+        pool.clone()
+    };
+    let prepared = PreparedFile {
+        dump,
+        pool,
+        resume_offset,
+        create_tables: !keep_tables,
+        empty_tables: replace,
+    };
+    prepared.reset_tables()?;
+    Ok(Some(prepared))
+}
+
+impl<'f> PreparedFile<'f> {
+    /// Create the tables of the dump, or empty them, unless it is appended.
+    fn reset_tables(&self) -> Result<()> {
+        if self.create_tables {
+            db::init(&self.pool, self.dump.entity)?;
+        } else if self.empty_tables {
+            db::truncate(&self.pool, self.dump.entity)?;
+        }
+        Ok(())
+    }
+}
+
+/// Load a prepared dump, and swap its tables in with `--atomic-swap`.
+fn load_file(
+    opt: &Opt,
+    live_pool: &db::Pool,
+    prepared: PreparedFile,
+    entry: &mut manifest::FileEntry,
+    checkpoint: &mut Option<Checkpoint>,
+) -> Result<()> {
+    let PreparedFile {
+        mut dump,
+        pool,
+        resume_offset,
+        ..
+    } = prepared;
+    let (file, entity) = (dump.file, dump.entity);
+    let result = if opt.writer_threads > 0 {
+        thread::scope(|scope| {
+            let mut sink =
+                ThreadedSink::new(scope, opt.writer_threads, || db::PostgresSink::new(&pool));
+            let result = parse_records(opt, &mut dump, &mut sink, resume_offset, entry, checkpoint);
+            let written = sink.join();
+            result.and_then(|position| written.map(|_| position))
+        })
+    } else {
+        let mut sink = db::PostgresSink::new(&pool);
+        parse_records(opt, &mut dump, &mut sink, resume_offset, entry, checkpoint)
+    };
+    pool.close_pipes(entity.tables(), result.is_ok());
+    let position = result?;
+
+    if opt.dbopts.atomic_swap {
+        db::swap_tables(live_pool, entity)?;
+    }
+    save_checkpoint(opt, checkpoint, file, position)
+}
+
+/// Write the records of a dump to the sink of a load without Postgres.
+fn export_file(
+    opt: &Opt,
+    sink: &mut dyn Sink,
+    file: &Path,
+    entry: &mut manifest::FileEntry,
+) -> Result<()> {
+    let mut dump = Dump::open(file, opt)?;
+    entry.entity = Some(dump.entity.name().to_string());
+    parse_records(opt, &mut dump, sink, 0, entry, &mut None)?;
+    sink.flush()
+}
+
+/// Parse the records of a dump into the sink, from the record at
+/// `resume_offset` on, and return the position after the last one.
+fn parse_records(
+    opt: &Opt,
+    dump: &mut Dump,
+    sink: &mut dyn Sink,
+    resume_offset: usize,
+    entry: &mut manifest::FileEntry,
+    checkpoint: &mut Option<Checkpoint>,
+) -> Result<FilePosition> {
+    let Dump {
+        file,
+        xmlfile,
+        buf,
+        entity,
+        first_record,
+        read: bytes_read,
+        len,
+    } = dump;
+    let (file, entity) = (*file, *entity);
+    let text = Rc::new(parser::TextDecoder::new(&opt.parseropts));
+    let mut parser: Box<dyn parser::Parser> = match entity {
+        parser::Entity::Artist => Box::new(Loader::new(
+            artist::ArtistsParser::new(&opt.parseropts, text.clone()),
+            &opt.dbopts,
+            sink,
+        )),
+        parser::Entity::Label => Box::new(Loader::new(
+            label::LabelsParser::new(&opt.parseropts, text.clone()),
+            &opt.dbopts,
+            sink,
+        )),
+        parser::Entity::Master => {
+            let mut parser = master::MastersParser::new(&opt.parseropts, text.clone());
+            if opt.dbopts.merge_genres_styles {
+                parser.merge_genres_styles();
+            }
+            Box::new(Loader::new(parser, &opt.dbopts, sink))
+        }
+        parser::Entity::Release => {
+            let mut parser = release::ReleasesParser::new(&opt.parseropts, text.clone());
+            if opt.dbopts.merge_genres_styles {
+                parser.merge_genres_styles();
+            }
+            if let Some(n) = opt.parseropts.top_referenced {
+                if file == Path::new(STDIN) {
+                    return Err(DiscogsLoadError::Config(
+                        "--top-referenced reads the dump twice, which isn't possible from stdin"
+                            .to_string(),
+                    ));
+                }
+                let (mut xmlfile, _) = open_dump(file, opt)?;
+                parser.keep_only(release::TopReferenced::count(&mut xmlfile, n)?);
+            }
+            Box::new(Loader::new(parser, &opt.dbopts, sink))
         }
+    };
+
+    // Parse and insert file
+    buf.clear();
+    if opt.dry_run {
+        info!("Parsing: {:?}", file.file_name().unwrap());
+    } else {
+        info!("Parsing and inserting: {:?}", file.file_name().unwrap());
+    }
+    let mut root_closed = false;
+    // records are the children of the root, or the top level elements of a
+    // fragment without one
+    let has_root = first_record.is_none();
+    let record_depth = if has_root { 1 } else { 0 };
+    // the root was read already
+    let mut depth = record_depth;
+    // the first record of a fragment was read while looking for the root
+    let mut pending = first_record.take();
+    let mut batches = parser.batches();
+    let mut progress = progress::Progress::new(
+        bytes_read.clone(),
+        *len,
+        opt.expected_records,
+        opt.no_progress,
+    );
+    let on_error = opt.parseropts.on_error;
+    let mut skipped = 0;
+    let mut skipping = false;
+    let result = loop {
+        let (event_start, read) = match pending.take() {
+            Some((ev, event_start)) => (event_start, Ok(ev)),
+            None => (xmlfile.buffer_position(), xmlfile.read_event(buf)),
+        };
+        match read {
+            // a fragment without the dump root, or a dump cut off before its
+            // end, still has its last batch to write
+            Ok(Event::Eof) if !root_closed => break parser.finish(),
+            Ok(Event::Eof) => break Ok(()),
+            Ok(ev) => {
+                let ev = match parser::text_event(ev) {
+                    Some(ev) => ev,
+                    None => {
+                        buf.clear();
+                        continue;
+                    }
+                };
+                let record_end = match ev {
+                    Event::Start(_) => {
+                        depth += 1;
+                        false
+                    }
+                    Event::End(ref e) => {
+                        root_closed |= e.local_name() == entity.root();
+                        depth -= 1;
+                        depth == record_depth
+                    }
+                    _ => false,
+                };
+                // the records written before the checkpoint are skipped
+                if event_start < resume_offset {
+                    buf.clear();
+                    continue;
+                }
+                text.set_position(event_start, xmlfile.buffer_position());
+                let processed = if skipping { Ok(()) } else { parser.process(ev) };
+                match processed {
+                    Err(e) if on_error == OnError::Skip && e.in_content() => {
+                        warn!("Skipping a {}: {}", entity.name(), e);
+                        skipped += 1;
+                        skipping = true;
+                    }
+                    Err(e) => break Err(e),
+                    Ok(()) => {}
+                }
+                progress.update(parser.records(), entity);
+                // the rest of a record that failed is passed by, and the
+                // record dropped at its end
+                if skipping && record_end {
+                    parser.discard();
+                    text.discard_record();
+                    skipping = false;
+                }
+                if parser.batches() != batches {
+                    batches = parser.batches();
+                    let position = FilePosition {
+                        offset: xmlfile.buffer_position(),
+                        last_id: parser.current_id(),
+                        complete: false,
+                    };
+                    if let Err(e) = save_checkpoint(opt, checkpoint, file, position) {
+                        break Err(e);
+                    }
+                }
+                match opt.limit_bytes {
+                    Some(limit) if record_end && xmlfile.buffer_position() >= limit => {
+                        info!("Stopping after {} bytes, --limit-bytes reached", limit);
+                        break parser.finish();
+                    }
+                    _ => {}
+                }
+                match opt.limit {
+                    Some(limit) if record_end && parser.records() >= limit => {
+                        info!("Stopping after {} records, --limit reached", limit);
+                        break parser.finish();
+                    }
+                    _ => {}
+                }
+            }
+            Err(e) => break Err(e.into()),
+        };
+        buf.clear();
+    };
+    progress.finish(parser.records(), entity);
+    entry.records = parser.records();
+    if skipped > 0 {
+        warn!(
+            "Skipped {} {} records that could not be parsed",
+            skipped,
+            entity.name()
+        );
     }
+    if opt.parseropts.coverage_report {
+        parser.coverage().report(entity);
+    }
+    result?;
+
+    Ok(FilePosition {
+        offset: xmlfile.buffer_position(),
+        last_id: parser.current_id(),
+        complete: true,
+    })
+}
+
+/// Record how far a file got in the `--checkpoint` file.
+fn save_checkpoint(
+    opt: &Opt,
+    checkpoint: &mut Option<Checkpoint>,
+    file: &Path,
+    position: FilePosition,
+) -> Result<()> {
+    if let (Some(checkpoint), Some(path)) = (checkpoint, &opt.checkpoint) {
+        checkpoint
+            .files
+            .insert(file.display().to_string(), position);
+        checkpoint.save(path)?;
+    }
+    Ok(())
+}
 
-    if opt.dbopts.create_indexes {
-        db::indexes(&opt.dbopts, "sql/indexes.sql")?;
+/// Parse the dump like a load, but compare its records with a sample of the
+/// rows in the tables.
+fn verify(opt: &Opt, dump: &Path) -> Result<()> {
+    if opt.dbopts.atomic_swap {
+        return Err(DiscogsLoadError::Config(
+            "--verify-against compares with the live tables and can't be combined with --atomic-swap"
+                .to_string(),
+        ));
+    }
+    let pool = db::pool(&opt.dbopts)?.verifying(opt.verify_sample);
+    let mut entry = manifest::FileEntry::start(dump);
+    read_file(opt, &pool, dump, &mut entry, &mut None)?;
+    let (compared, differences) = pool.verify_report().unwrap();
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    if differences.is_empty() {
+        println!("The {} sampled rows match the dump.", compared);
+        return Ok(());
     }
+    Err(DiscogsLoadError::Mismatch(format!(
+        "{} differences between the tables and the dump, {} rows compared.",
+        differences.len(),
+        compared
+    )))
+}
 
+fn schema_diff(opt: &Opt) -> Result<()> {
+    let pool = db::pool(&opt.dbopts)?;
+    let differences = db::schema_diff(&pool)?;
+    if differences.is_empty() {
+        println!("The tables match the current schema.");
+        return Ok(());
+    }
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    Err(DiscogsLoadError::Mismatch(
+        "The tables differ from the current schema, reload the affected files to recreate them."
+            .to_string(),
+    ))
+}
+
+/// Parse a byte count like `500000`, `64KB`, `100MB` or `2GB`, the units are
+/// powers of 1024.
+fn parse_bytes(s: &str) -> std::result::Result<usize, String> {
+    let s = s.trim();
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (number, unit) = s.split_at(digits);
+    let number: usize = number
+        .parse()
+        .map_err(|_| format!("invalid byte count: {}", s))?;
+    let multiplier = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1 << 10,
+        "M" | "MB" => 1 << 20,
+        "G" | "GB" => 1 << 30,
+        _ => return Err(format!("unknown byte unit: {}", unit)),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("byte count too large: {}", s))
+}
+
+fn gen_migration(opt: &Opt, out: &Path) -> Result<()> {
+    fs::write(out, db::migration_sql(&opt.dbopts, "sql/indexes.sql")?)?;
+    info!("Wrote the migration script to {:?}", out);
+    Ok(())
+}
+
+fn print_schema(opt: &Opt, entity: Option<parser::Entity>) -> Result<()> {
+    let entities = match entity {
+        Some(entity) => vec![entity],
+        None => parser::Entity::ALL.to_vec(),
+    };
+    print!("{}", db::tables_sql(&opt.dbopts, &entities)?);
+    Ok(())
+}
+
+fn write_manifest(opt: &Opt, manifest: &manifest::Manifest) -> Result<()> {
+    if let Some(path) = &opt.manifest_out {
+        manifest.write(path)?;
+    }
     Ok(())
 }
@@ -0,0 +1,69 @@
+use serde::Serialize;
+use std::{collections::BTreeMap, fs, io, path::Path, time::SystemTime};
+
+use crate::error::Result;
+
+/// Machine-readable record of a run, written with `--manifest-out`.
+#[derive(Debug, Default, Serialize)]
+pub struct Manifest {
+    pub files: Vec<FileEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileEntry {
+    pub file: String,
+    pub entity: Option<String>,
+    pub records: u64,
+    pub rows: BTreeMap<String, u64>,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+impl FileEntry {
+    pub fn start(file: &Path) -> Self {
+        FileEntry {
+            file: file.display().to_string(),
+            entity: None,
+            records: 0,
+            rows: BTreeMap::new(),
+            started_at: now(),
+            finished_at: None,
+            success: false,
+            error: None,
+        }
+    }
+
+    /// Close the entry, `rows_before` and `rows_after` are the row counter
+    /// snapshots taken around the load of this file.
+    pub fn finish<E: std::fmt::Display>(
+        &mut self,
+        result: &Result<(), E>,
+        rows_before: &BTreeMap<String, u64>,
+        rows_after: &BTreeMap<String, u64>,
+    ) {
+        self.finished_at = Some(now());
+        self.rows = rows_after
+            .iter()
+            .map(|(table, rows)| (table.clone(), rows - rows_before.get(table).unwrap_or(&0)))
+            .filter(|(_, rows)| *rows > 0)
+            .collect();
+        match result {
+            Ok(()) => self.success = true,
+            Err(e) => self.error = Some(e.to_string()),
+        }
+    }
+}
+
+impl Manifest {
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::from)?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+fn now() -> String {
+    humantime::format_rfc3339_seconds(SystemTime::now()).to_string()
+}
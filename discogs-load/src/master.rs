@@ -1,67 +1,108 @@
-use indicatif::ProgressBar;
 use postgres::types::ToSql;
 use quick_xml::events::Event;
-use std::{collections::HashMap, error::Error, str};
-
-use crate::db::{write_masters, DbOpt, SqlSerialization};
-use crate::parser::Parser;
-
-#[derive(Clone, Debug)]
+use serde::Serialize;
+use std::{mem, rc::Rc, str};
+
+use crate::db::SqlSerialization;
+use crate::error::Result;
+use crate::parser::{
+    attribute, merge_tags, required_attribute, Coverage, DebugSampler, Entity, MissingInt,
+    ParserOpt, Record, RecordParser, TextDecoder,
+};
+use crate::sink::{rows, Sink, TableWriter};
+use crate::transform::{apply_all, dedup_arrays, Transform, Transformable};
+
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct Master {
-    pub id: i32,
-    pub title: String,
-    pub release_id: i32,
-    pub year: i32,
-    pub notes: String,
+    pub id: i64,
+    pub title: Option<String>,
+    pub release_id: Option<i64>,
+    pub year: Option<i32>,
+    pub notes: Option<String>,
     pub genres: Vec<String>,
     pub styles: Vec<String>,
-    pub data_quality: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    pub data_quality: Option<String>,
+    pub source_offset: Option<i64>,
+    pub master_artists: Vec<MasterArtist>,
+    pub master_videos: Vec<MasterVideo>,
 }
 
 impl SqlSerialization for Master {
-    fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
-        let row: Vec<&'_ (dyn ToSql + Sync)> = vec![
-            &self.id,
-            &self.title,
-            &self.release_id,
-            &self.year,
-            &self.notes,
-            &self.genres,
-            &self.styles,
-            &self.data_quality,
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let mut row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("id", &self.id),
+            ("title", &self.title),
+            ("release_id", &self.release_id),
+            ("year", &self.year),
+            ("notes", &self.notes),
         ];
+        match &self.tags {
+            Some(tags) => row.push(("tags", tags)),
+            None => {
+                row.push(("genres", &self.genres));
+                row.push(("styles", &self.styles));
+            }
+        }
+        row.push(("data_quality", &self.data_quality));
+        row.push(("source_offset", &self.source_offset));
         row
     }
 }
 
+impl Transformable for Master {
+    fn text_field(&mut self, name: &str) -> Option<&mut String> {
+        match name {
+            "title" => self.title.as_mut(),
+            "notes" => self.notes.as_mut(),
+            "data_quality" => self.data_quality.as_mut(),
+            _ => None,
+        }
+    }
+
+    fn text_arrays(&mut self) -> Vec<&mut Vec<String>> {
+        vec![&mut self.genres, &mut self.styles]
+    }
+}
+
 impl Master {
     pub fn new() -> Self {
         Master {
             id: 0,
-            title: String::new(),
-            release_id: 0,
-            year: 0,
-            notes: String::new(),
+            title: None,
+            release_id: None,
+            year: None,
+            notes: None,
             genres: Vec::new(),
             styles: Vec::new(),
-            data_quality: String::new(),
+            tags: None,
+            data_quality: None,
+            source_offset: None,
+            master_artists: Vec::new(),
+            master_videos: Vec::new(),
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct MasterArtist {
-    pub id: i32,
-    pub master_id: i32,
+    pub id: i64,
+    pub master_id: i64,
     pub name: String,
     pub anv: String,
     pub role: String,
 }
 
 impl SqlSerialization for MasterArtist {
-    fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
-        let row: Vec<&'_ (dyn ToSql + Sync)> =
-            vec![&self.id, &self.master_id, &self.name, &self.anv, &self.role];
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("artist_id", &self.id),
+            ("master_id", &self.master_id),
+            ("name", &self.name),
+            ("anv", &self.anv),
+            ("role", &self.role),
+        ];
         row
     }
 }
@@ -78,6 +119,37 @@ impl MasterArtist {
     }
 }
 
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MasterVideo {
+    pub master_id: i64,
+    pub duration: i32,
+    pub src: String,
+    pub title: String,
+}
+
+impl MasterVideo {
+    pub fn new() -> Self {
+        MasterVideo {
+            master_id: 0,
+            duration: 0,
+            src: String::new(),
+            title: String::new(),
+        }
+    }
+}
+
+impl SqlSerialization for MasterVideo {
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("master_id", &self.master_id),
+            ("duration", &self.duration),
+            ("src", &self.src),
+            ("title", &self.title),
+        ];
+        row
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 enum ParserReadState {
     // master
@@ -85,100 +157,181 @@ enum ParserReadState {
     MainRelease,
     Artists,
     Title,
+    Genres,
+    Genre,
+    Styles,
+    Style,
     DataQuality,
     // master_artists
     ArtistId,
     ArtistName,
     ArtistAnv,
     ArtistRole,
+    // master_video
+    Videos,
+    Video,
+    VideoTitle,
+}
+
+impl Record for Master {
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn write(sink: &mut dyn Sink, batch: Vec<Self>) -> Result<()> {
+        sink.take_masters(batch)
+    }
+
+    fn write_tables(batch: &[Self], tables: &mut dyn TableWriter) -> Result<()> {
+        tables.write_rows("master", &mut rows(batch))?;
+        tables.write_rows(
+            "master_artist",
+            &mut rows(batch.iter().flat_map(|m| &m.master_artists)),
+        )?;
+        tables.write_rows(
+            "master_video",
+            &mut rows(batch.iter().flat_map(|m| &m.master_videos)),
+        )
+    }
 }
 
-pub struct MastersParser<'a> {
+pub struct MastersParser {
     state: ParserReadState,
-    masters: HashMap<i32, Master>,
     current_master: Master,
     current_artist: MasterArtist,
-    current_master_id: i32,
-    master_artists: HashMap<i32, MasterArtist>,
-    pb: ProgressBar,
-    db_opts: &'a DbOpt,
+    current_video: MasterVideo,
+    records: u64,
+    sampler: DebugSampler,
+    transforms: Vec<Transform>,
+    coverage: Coverage,
+    with_source_offset: bool,
+    dedup_arrays: bool,
+    merge_genres_styles: bool,
+    text: Rc<TextDecoder>,
+    missing_int: MissingInt,
 }
 
-impl<'a> MastersParser<'a> {
-    pub fn new(db_opts: &'a DbOpt) -> Self {
+impl RecordParser for MastersParser {
+    type Record = Master;
+    const ENTITY: Entity = Entity::Master;
+
+    fn new(parser_opts: &ParserOpt, text: Rc<TextDecoder>) -> Self {
         MastersParser {
             state: ParserReadState::Master,
-            masters: HashMap::new(),
             current_master: Master::new(),
             current_artist: MasterArtist::new(),
-            current_master_id: 0,
-            master_artists: HashMap::new(),
-            pb: ProgressBar::new(1821993),
-            db_opts,
+            current_video: MasterVideo::new(),
+            records: 0,
+            sampler: DebugSampler::new(parser_opts.debug_sample),
+            transforms: parser_opts.transforms.clone(),
+            coverage: Coverage::new(parser_opts),
+            with_source_offset: parser_opts.with_source_offset,
+            dedup_arrays: parser_opts.dedup_arrays,
+            merge_genres_styles: false,
+            text,
+            missing_int: parser_opts.missing_int_as,
         }
     }
+
+    fn records(&self) -> u64 {
+        self.records
+    }
+
+    fn coverage(&self) -> &Coverage {
+        &self.coverage
+    }
+
+    fn discard(&mut self) {
+        self.records += 1;
+        self.state = ParserReadState::Master;
+    }
+
+    fn current_id(&self) -> i64 {
+        self.current_master.id
+    }
+
+    fn process(&mut self, ev: Event) -> Result<Option<Master>> {
+        self.read(ev)
+            .map_err(|e| e.in_record("master", self.current_master.id))
+    }
 }
 
-impl<'a> Parser<'a> for MastersParser<'a> {
-    fn new(&self, db_opts: &'a DbOpt) -> Self {
-        MastersParser {
-            state: ParserReadState::Master,
-            masters: HashMap::new(),
-            current_master: Master::new(),
-            current_artist: MasterArtist::new(),
-            current_master_id: 0,
-            master_artists: HashMap::new(),
-            pb: ProgressBar::new(1821993),
-            db_opts,
-        }
+impl MastersParser {
+    /// Fill the `tags` of the masters instead of their genres and styles, for
+    /// `--merge-genres-styles`.
+    pub fn merge_genres_styles(&mut self) {
+        self.merge_genres_styles = true;
     }
-    fn process(&mut self, ev: Event) -> Result<(), Box<dyn Error>> {
+
+    fn read(&mut self, ev: Event) -> Result<Option<Master>> {
+        let mut ended = None;
         self.state = match self.state {
-            ParserReadState::Master => {
-                match ev {
-                    Event::Start(e) if e.local_name() == b"master" => {
-                        self.current_master.genres = Vec::new();
-                        self.current_master.styles = Vec::new();
-                        self.current_master.id = str::parse(str::from_utf8(
-                            &e.attributes().next().unwrap()?.unescaped_value()?,
-                        )?)?;
-                        ParserReadState::Master
+            ParserReadState::Master => match ev {
+                Event::Start(e) if e.local_name() == b"master" => {
+                    self.current_master = Master::new();
+                    if self.with_source_offset {
+                        self.current_master.source_offset = Some(self.text.event_start() as i64);
                     }
+                    self.current_master.id = str::parse(&required_attribute(&e, b"id")?)?;
+                    ParserReadState::Master
+                }
 
-                    Event::Start(e) => match e.local_name() {
+                Event::Start(e) => {
+                    let state = match e.local_name() {
                         b"main_release" => ParserReadState::MainRelease,
                         b"title" => ParserReadState::Title,
                         b"artists" => ParserReadState::Artists,
+                        b"genres" => ParserReadState::Genres,
+                        b"styles" => ParserReadState::Styles,
                         b"data_quality" => ParserReadState::DataQuality,
+                        b"videos" => ParserReadState::Videos,
                         _ => ParserReadState::Master,
-                    },
-
-                    Event::End(e) if e.local_name() == b"master" => {
-                        self.masters
-                            .entry(self.current_master.id)
-                            .or_insert(self.current_master.clone());
-                        if self.masters.len() >= self.db_opts.batch_size {
-                            write_masters(self.db_opts, &self.masters, &self.master_artists)?;
-                            self.masters = HashMap::new();
-                            self.master_artists = HashMap::new();
-                        }
-                        self.pb.inc(1);
-                        ParserReadState::Master
-                    }
+                    };
+                    self.coverage
+                        .saw(e.local_name(), !matches!(state, ParserReadState::Master));
+                    state
+                }
 
-                    Event::End(e) if e.local_name() == b"masters" => {
-                        // write to db remainder of masters
-                        write_masters(self.db_opts, &self.masters, &self.master_artists)?;
-                        ParserReadState::Master
-                    }
+                Event::End(e)
+                    if e.local_name() == b"master"
+                        && self.text.skip_record("master", self.current_master.id) =>
+                {
+                    self.discard();
+                    ParserReadState::Master
+                }
 
-                    _ => ParserReadState::Master,
+                Event::End(e) if e.local_name() == b"master" => {
+                    apply_all(&self.transforms, &mut self.current_master);
+                    if self.dedup_arrays {
+                        dedup_arrays(&mut self.current_master);
+                    }
+                    self.current_master.release_id =
+                        self.missing_int.apply(self.current_master.release_id);
+                    if self.merge_genres_styles {
+                        self.current_master.tags = Some(merge_tags(
+                            &self.current_master.genres,
+                            &self.current_master.styles,
+                        ));
+                    }
+                    self.sampler.sample(&self.current_master);
+                    let id = self.current_master.id;
+                    ended = Some(mem::replace(
+                        &mut self.current_master,
+                        Master {
+                            id,
+                            ..Master::new()
+                        },
+                    ));
+                    self.records += 1;
+                    ParserReadState::Master
                 }
-            }
+
+                _ => ParserReadState::Master,
+            },
 
             ParserReadState::MainRelease => match ev {
                 Event::Text(e) => {
-                    self.current_master.release_id = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_master.release_id = Some(str::parse(&self.text.decode(&e)?)?);
                     ParserReadState::MainRelease
                 }
 
@@ -203,10 +356,10 @@ impl<'a> Parser<'a> for MastersParser<'a> {
 
                 Event::End(e) => match e.local_name() {
                     b"artist" => {
-                        self.master_artists
-                            .entry(self.current_master_id)
-                            .or_insert(self.current_artist.clone());
-                        self.current_master_id += 1;
+                        self.sampler.sample(&self.current_artist);
+                        self.current_master
+                            .master_artists
+                            .push(mem::replace(&mut self.current_artist, MasterArtist::new()));
                         ParserReadState::Artists
                     }
                     b"artists" => ParserReadState::Master,
@@ -218,7 +371,7 @@ impl<'a> Parser<'a> for MastersParser<'a> {
 
             ParserReadState::ArtistId => match ev {
                 Event::Text(e) => {
-                    self.current_artist.id = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_artist.id = str::parse(&self.text.decode(&e)?)?;
                     ParserReadState::Artists
                 }
 
@@ -229,7 +382,7 @@ impl<'a> Parser<'a> for MastersParser<'a> {
 
             ParserReadState::ArtistName => match ev {
                 Event::Text(e) => {
-                    self.current_artist.name = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_artist.name = str::parse(&self.text.decode(&e)?)?;
                     ParserReadState::Artists
                 }
 
@@ -240,7 +393,7 @@ impl<'a> Parser<'a> for MastersParser<'a> {
 
             ParserReadState::ArtistAnv => match ev {
                 Event::Text(e) => {
-                    self.current_artist.anv = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_artist.anv = str::parse(&self.text.decode(&e)?)?;
                     ParserReadState::Artists
                 }
 
@@ -251,7 +404,7 @@ impl<'a> Parser<'a> for MastersParser<'a> {
 
             ParserReadState::ArtistRole => match ev {
                 Event::Text(e) => {
-                    self.current_artist.role = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_artist.role = str::parse(&self.text.decode(&e)?)?;
                     ParserReadState::Artists
                 }
 
@@ -262,7 +415,7 @@ impl<'a> Parser<'a> for MastersParser<'a> {
 
             ParserReadState::Title => match ev {
                 Event::Text(e) => {
-                    self.current_master.title = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_master.title = Some(self.text.decode(&e)?);
                     ParserReadState::Title
                 }
 
@@ -271,10 +424,47 @@ impl<'a> Parser<'a> for MastersParser<'a> {
                 _ => ParserReadState::Title,
             },
 
+            ParserReadState::Genres => match ev {
+                Event::Start(e) if e.local_name() == b"genre" => ParserReadState::Genre,
+
+                Event::End(e) if e.local_name() == b"genres" => ParserReadState::Master,
+
+                _ => ParserReadState::Genres,
+            },
+
+            ParserReadState::Genre => match ev {
+                Event::Text(e) => {
+                    self.current_master
+                        .genres
+                        .extend(str::parse(&self.text.decode(&e)?));
+                    ParserReadState::Genres
+                }
+
+                _ => ParserReadState::Genres,
+            },
+
+            ParserReadState::Styles => match ev {
+                Event::Start(e) if e.local_name() == b"style" => ParserReadState::Style,
+
+                Event::End(e) if e.local_name() == b"styles" => ParserReadState::Master,
+
+                _ => ParserReadState::Styles,
+            },
+
+            ParserReadState::Style => match ev {
+                Event::Text(e) => {
+                    self.current_master
+                        .styles
+                        .extend(str::parse(&self.text.decode(&e)?));
+                    ParserReadState::Styles
+                }
+
+                _ => ParserReadState::Styles,
+            },
+
             ParserReadState::DataQuality => match ev {
                 Event::Text(e) => {
-                    self.current_master.data_quality =
-                        str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_master.data_quality = Some(self.text.decode(&e)?);
                     ParserReadState::DataQuality
                 }
 
@@ -282,8 +472,53 @@ impl<'a> Parser<'a> for MastersParser<'a> {
 
                 _ => ParserReadState::DataQuality,
             },
+
+            ParserReadState::Videos => match ev {
+                Event::Start(e) if e.local_name() == b"video" => {
+                    self.current_video = MasterVideo {
+                        master_id: self.current_master.id,
+                        duration: match attribute(&e, b"duration")? {
+                            Some(duration) => str::parse(&duration)?,
+                            None => 0,
+                        },
+                        src: self.text.attribute(&e, b"src")?.unwrap_or_default(),
+                        title: String::new(),
+                    };
+                    ParserReadState::Video
+                }
+
+                Event::End(e) if e.local_name() == b"videos" => ParserReadState::Master,
+
+                _ => ParserReadState::Videos,
+            },
+
+            ParserReadState::Video => match ev {
+                Event::Start(e) if e.local_name() == b"title" => ParserReadState::VideoTitle,
+
+                // videos without a title keep an empty one
+                Event::End(e) if e.local_name() == b"video" => {
+                    self.sampler.sample(&self.current_video);
+                    self.current_master
+                        .master_videos
+                        .push(mem::replace(&mut self.current_video, MasterVideo::new()));
+                    ParserReadState::Videos
+                }
+
+                _ => ParserReadState::Video,
+            },
+
+            ParserReadState::VideoTitle => match ev {
+                Event::Text(e) => {
+                    self.current_video.title = self.text.decode(&e)?;
+                    ParserReadState::VideoTitle
+                }
+
+                Event::End(e) if e.local_name() == b"title" => ParserReadState::Video,
+
+                _ => ParserReadState::VideoTitle,
+            },
         };
 
-        Ok(())
+        Ok(ended)
     }
 }
@@ -0,0 +1,198 @@
+use arrow_array::{
+    builder::{BooleanBuilder, Int32Builder, Int64Builder, ListBuilder, StringBuilder},
+    ArrayRef, RecordBatch,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use parquet::{
+    arrow::ArrowWriter, basic::Compression, errors::ParquetError,
+    file::properties::WriterProperties,
+};
+use postgres::types::Type;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::artist::Artist;
+use crate::db::{DbOpt, SqlSerialization, TableColumns, Value};
+use crate::error::Result;
+use crate::label::Label;
+use crate::master::Master;
+use crate::parser::Record;
+use crate::release::Release;
+use crate::sink::{Sink, TableWriter};
+
+/// Writes the tables to one Parquet file each in a directory,
+/// `release.parquet`, `release_label.parquet` and so on, for
+/// `--output-format parquet`.
+///
+/// The columns are the ones the loader copies into the tables, arrays like
+/// the genres being lists of strings. Every batch is a row group of its own,
+/// so no more than a batch is kept in memory. The files are only complete
+/// once the sink is finished.
+pub struct ParquetSink {
+    dir: PathBuf,
+    columns: TableColumns,
+    files: HashMap<&'static str, (SchemaRef, ArrowWriter<File>)>,
+    rows: BTreeMap<String, u64>,
+}
+
+impl ParquetSink {
+    /// Create the directory if it is missing. The file of a table is
+    /// created, or replaced, with the first batch of its entity.
+    pub fn new(dir: &Path, db_opts: &DbOpt) -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(ParquetSink {
+            dir: dir.to_path_buf(),
+            columns: TableColumns::new(db_opts),
+            files: HashMap::new(),
+            rows: BTreeMap::new(),
+        })
+    }
+
+    fn file(&mut self, table: &'static str) -> Result<&mut (SchemaRef, ArrowWriter<File>)> {
+        if !self.files.contains_key(table) {
+            let fields: Vec<Field> = self
+                .columns
+                .columns(table)
+                .iter()
+                .map(|(column, col_type)| Field::new(*column, data_type(col_type), true))
+                .collect();
+            let schema = Arc::new(Schema::new(fields));
+            let file = File::create(self.dir.join(format!("{}.parquet", table)))?;
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build();
+            let writer = ArrowWriter::try_new(file, schema.clone(), Some(properties))?;
+            self.files.insert(table, (schema, writer));
+        }
+        Ok(self.files.get_mut(table).unwrap())
+    }
+}
+
+impl TableWriter for ParquetSink {
+    fn write_rows(
+        &mut self,
+        table: &'static str,
+        rows: &mut dyn Iterator<Item = &dyn SqlSerialization>,
+    ) -> Result<()> {
+        let mut builders: Vec<ColumnBuilder> = self
+            .columns
+            .columns(table)
+            .iter()
+            .map(|(_, col_type)| ColumnBuilder::new(col_type))
+            .collect();
+        let mut written = 0;
+        for row in rows {
+            for (builder, value) in builders.iter_mut().zip(self.columns.values(table, row)?) {
+                builder.append(value);
+            }
+            written += 1;
+        }
+        let (schema, writer) = self.file(table)?;
+        if written > 0 {
+            let arrays = builders.iter_mut().map(ColumnBuilder::finish).collect();
+            let batch = RecordBatch::try_new(schema.clone(), arrays).map_err(ParquetError::from)?;
+            writer.write(&batch)?;
+            writer.flush()?;
+        }
+        *self.rows.entry(table.to_string()).or_insert(0) += written;
+        Ok(())
+    }
+}
+
+impl Sink for ParquetSink {
+    fn write_artists(&mut self, batch: &[Artist]) -> Result<()> {
+        Artist::write_tables(batch, self)
+    }
+
+    fn write_labels(&mut self, batch: &[Label]) -> Result<()> {
+        Label::write_tables(batch, self)
+    }
+
+    fn write_masters(&mut self, batch: &[Master]) -> Result<()> {
+        Master::write_tables(batch, self)
+    }
+
+    fn write_releases(&mut self, batch: &[Release]) -> Result<()> {
+        Release::write_tables(batch, self)
+    }
+
+    /// Write the footers of the files, which can't be written to afterwards.
+    fn finish(&mut self) -> Result<()> {
+        for (_, (_, writer)) in mem::take(&mut self.files) {
+            writer.close()?;
+        }
+        Ok(())
+    }
+
+    fn row_counts(&self) -> BTreeMap<String, u64> {
+        self.rows.clone()
+    }
+}
+
+/// The Arrow type of a column of the Postgres tables.
+fn data_type(col_type: &Type) -> DataType {
+    match *col_type {
+        Type::INT8 => DataType::Int64,
+        Type::INT4 => DataType::Int32,
+        Type::BOOL => DataType::Boolean,
+        Type::TEXT_ARRAY => DataType::List(Arc::new(Field::new_list_field(DataType::Utf8, true))),
+        _ => DataType::Utf8,
+    }
+}
+
+/// Collects the values of a column of a batch.
+enum ColumnBuilder {
+    Int64(Int64Builder),
+    Int32(Int32Builder),
+    Boolean(BooleanBuilder),
+    Utf8(StringBuilder),
+    List(ListBuilder<StringBuilder>),
+}
+
+impl ColumnBuilder {
+    fn new(col_type: &Type) -> Self {
+        match *col_type {
+            Type::INT8 => ColumnBuilder::Int64(Int64Builder::new()),
+            Type::INT4 => ColumnBuilder::Int32(Int32Builder::new()),
+            Type::BOOL => ColumnBuilder::Boolean(BooleanBuilder::new()),
+            Type::TEXT_ARRAY => ColumnBuilder::List(ListBuilder::new(StringBuilder::new())),
+            _ => ColumnBuilder::Utf8(StringBuilder::new()),
+        }
+    }
+
+    /// Add a value, the values decoded for a column always match its type.
+    fn append(&mut self, value: Value) {
+        match (self, value) {
+            (ColumnBuilder::Int64(builder), Value::Int(n)) => builder.append_value(n),
+            (ColumnBuilder::Int32(builder), Value::Int(n)) => builder.append_value(n as i32),
+            (ColumnBuilder::Boolean(builder), Value::Bool(b)) => builder.append_value(b),
+            (ColumnBuilder::Utf8(builder), Value::Text(text)) => builder.append_value(text),
+            (ColumnBuilder::List(builder), Value::TextArray(elements)) => {
+                for element in elements {
+                    builder.values().append_value(element);
+                }
+                builder.append(true);
+            }
+            (ColumnBuilder::Int64(builder), _) => builder.append_null(),
+            (ColumnBuilder::Int32(builder), _) => builder.append_null(),
+            (ColumnBuilder::Boolean(builder), _) => builder.append_null(),
+            (ColumnBuilder::Utf8(builder), _) => builder.append_null(),
+            (ColumnBuilder::List(builder), _) => builder.append_null(),
+        }
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            ColumnBuilder::Int64(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Int32(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Boolean(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::Utf8(builder) => Arc::new(builder.finish()),
+            ColumnBuilder::List(builder) => Arc::new(builder.finish()),
+        }
+    }
+}
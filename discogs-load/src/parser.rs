@@ -1,11 +1,649 @@
-use quick_xml::events::Event;
-use std::error::Error;
+use log::{debug, info, warn};
+use quick_xml::events::{BytesStart, BytesText, Event};
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Debug,
+    mem,
+    rc::Rc,
+    str::{self, FromStr},
+    time::{Duration, Instant},
+};
+use structopt::StructOpt;
 
 use crate::db::DbOpt;
+use crate::error::{DiscogsLoadError, Result};
+use crate::sink::{Sink, TableWriter};
+use crate::transform::Transform;
 
-pub trait Parser<'a> {
-    fn new(&self, db_opts: &'a DbOpt) -> Self
+#[derive(Debug, Clone, StructOpt)]
+pub struct ParserOpt {
+    /// Print the first K parsed records of every type to stderr
+    #[structopt(long = "debug-sample", default_value = "0")]
+    pub debug_sample: usize,
+    /// How to store a referenced id missing from the dump
+    #[structopt(
+        long = "missing-int-as",
+        default_value = "null",
+        possible_values = &["null", "zero", "minus-one"]
+    )]
+    pub missing_int_as: MissingInt,
+    /// Skip records holding invalid UTF-8 instead of loading them with
+    /// replacement characters
+    #[structopt(long = "skip-errors")]
+    pub skip_errors: bool,
+    /// What to do with a record that can't be parsed, like one with an id
+    /// that isn't a number, `skip` leaves it out and goes on with the next
+    #[structopt(
+        long = "on-error",
+        default_value = "abort",
+        possible_values = &["abort", "skip"]
+    )]
+    pub on_error: OnError,
+    /// Parse the files as this entity, for files without a dump root element
+    #[structopt(
+        long = "force-entity",
+        possible_values = &["artist", "label", "master", "release"]
+    )]
+    pub force_entity: Option<Entity>,
+    /// Fill the release `is_compilation` column, set for releases by Various
+    /// Artists or with a Compilation format
+    #[structopt(long = "with-compilation-flag")]
+    pub with_compilation_flag: bool,
+    /// Fill the `source_offset` column of the artist, label, master and
+    /// release tables with the byte offset of the record in the decompressed
+    /// dump
+    #[structopt(long = "with-source-offset")]
+    pub with_source_offset: bool,
+    /// Rewrite a text column of every record before it is written, e.g.
+    /// 'country=upper(country)', with the functions upper, lower, trim and
+    /// replace(column, from, to), can be repeated
+    #[structopt(long = "transform", number_of_values = 1)]
+    pub transforms: Vec<Transform>,
+    /// Remove repeated values from the array columns of every record, like
+    /// genres and urls, keeping the first of them
+    #[structopt(long = "dedup-arrays")]
+    pub dedup_arrays: bool,
+    /// Only load the N releases whose master has the most releases, counted in
+    /// a first pass over the file
+    #[structopt(long = "top-referenced")]
+    pub top_referenced: Option<usize>,
+    /// Report which child elements of the records were seen and whether the
+    /// parser reads or ignores them
+    #[structopt(long = "coverage-report")]
+    pub coverage_report: bool,
+}
+
+impl Default for ParserOpt {
+    fn default() -> Self {
+        ParserOpt {
+            debug_sample: 0,
+            missing_int_as: MissingInt::Null,
+            skip_errors: false,
+            on_error: OnError::Abort,
+            force_entity: None,
+            with_compilation_flag: false,
+            with_source_offset: false,
+            transforms: Vec::new(),
+            dedup_arrays: false,
+            top_referenced: None,
+            coverage_report: false,
+        }
+    }
+}
+
+/// How an id referencing another record, like a release's master_id, is
+/// stored when the dump leaves it out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MissingInt {
+    Zero,
+    Null,
+    MinusOne,
+}
+
+impl MissingInt {
+    pub fn apply(self, value: Option<i64>) -> Option<i64> {
+        value.or(match self {
+            MissingInt::Zero => Some(0),
+            MissingInt::Null => None,
+            MissingInt::MinusOne => Some(-1),
+        })
+    }
+}
+
+impl FromStr for MissingInt {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(MissingInt::Zero),
+            "null" => Ok(MissingInt::Null),
+            "minus-one" => Ok(MissingInt::MinusOne),
+            _ => Err(format!("unknown missing int representation: {}", s)),
+        }
+    }
+}
+
+/// Whether a record that can't be parsed ends the load, for `--on-error`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnError {
+    Abort,
+    Skip,
+}
+
+impl FromStr for OnError {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(OnError::Abort),
+            "skip" => Ok(OnError::Skip),
+            _ => Err(format!("unknown error handling: {}", s)),
+        }
+    }
+}
+
+/// The kind of records a dump file holds, detected from its root element.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Entity {
+    Artist,
+    Label,
+    Master,
+    Release,
+}
+
+impl Entity {
+    pub const ALL: [Entity; 4] = [
+        Entity::Artist,
+        Entity::Label,
+        Entity::Master,
+        Entity::Release,
+    ];
+
+    pub fn from_root(name: &[u8]) -> Option<Self> {
+        match name {
+            b"artists" => Some(Entity::Artist),
+            b"labels" => Some(Entity::Label),
+            b"masters" => Some(Entity::Master),
+            b"releases" => Some(Entity::Release),
+            _ => None,
+        }
+    }
+
+    /// The root element of the entity's dump.
+    pub fn root(&self) -> &'static [u8] {
+        match self {
+            Entity::Artist => b"artists",
+            Entity::Label => b"labels",
+            Entity::Master => b"masters",
+            Entity::Release => b"releases",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Entity::Artist => "artist",
+            Entity::Label => "label",
+            Entity::Master => "master",
+            Entity::Release => "release",
+        }
+    }
+
+    pub fn schema_path(&self) -> &'static str {
+        match self {
+            Entity::Artist => "sql/tables/artist.sql",
+            Entity::Label => "sql/tables/label.sql",
+            Entity::Master => "sql/tables/master.sql",
+            Entity::Release => "sql/tables/release.sql",
+        }
+    }
+
+    /// Every table the entity's parser writes to.
+    pub fn tables(&self) -> &'static [&'static str] {
+        match self {
+            Entity::Artist => &["artist", "artist_member", "artist_alias"],
+            Entity::Label => &["label"],
+            Entity::Master => &["master", "master_artist", "master_video"],
+            Entity::Release => &[
+                "release",
+                "release_artist",
+                "release_extraartist",
+                "release_company",
+                "release_format",
+                "release_label",
+                "release_track",
+                "release_track_extraartist",
+                "release_identifier",
+                "release_video",
+            ],
+        }
+    }
+}
+
+impl FromStr for Entity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Entity::ALL
+            .iter()
+            .copied()
+            .find(|entity| entity.name() == s)
+            .ok_or_else(|| format!("unknown entity: {}", s))
+    }
+}
+
+/// A record of a dump, holding the rows of its child tables.
+pub trait Record: Debug {
+    fn id(&self) -> i64;
+
+    /// What the record counts towards the batch size.
+    fn batch_weight(&self, _batch_by: BatchBy) -> usize {
+        1
+    }
+
+    /// Write a batch of records with their child rows to the sink, which
+    /// takes the batch over.
+    fn write(sink: &mut dyn Sink, batch: Vec<Self>) -> Result<()>
     where
         Self: Sized;
-    fn process(&mut self, ev: Event) -> Result<(), Box<dyn Error>>;
+
+    /// Write the rows of a batch to every table of the entity, in the order
+    /// of `Entity::tables`.
+    fn write_tables(batch: &[Self], tables: &mut dyn TableWriter) -> Result<()>
+    where
+        Self: Sized;
+}
+
+/// Turns the events of a dump into records, without writing them anywhere.
+pub trait RecordParser {
+    type Record: Record;
+    const ENTITY: Entity;
+
+    fn new(parser_opts: &ParserOpt, text: Rc<TextDecoder>) -> Self;
+    /// Handle an event, returning the record it ended unless the record is
+    /// left out.
+    fn process(&mut self, ev: Event) -> Result<Option<Self::Record>>;
+    /// Drop the record being parsed, to go on with the next record after
+    /// `--on-error skip`.
+    fn discard(&mut self);
+    /// Number of records ended so far, the ones left out included.
+    fn records(&self) -> u64;
+    /// Id of the record being parsed, or the last one after its end.
+    fn current_id(&self) -> i64;
+    fn coverage(&self) -> &Coverage;
+}
+
+pub trait Parser {
+    fn process(&mut self, ev: Event) -> Result<()>;
+    /// Write the remainder of the records, at the end of the dump root or of
+    /// a file without one.
+    fn finish(&mut self) -> Result<()>;
+    /// Drop the record being parsed with the rows of it already in the batch,
+    /// to go on with the next record after `--on-error skip`.
+    fn discard(&mut self);
+    /// Number of entity records parsed so far.
+    fn records(&self) -> u64;
+    /// Number of full batches written so far, without the remainder written
+    /// at the end of the file.
+    fn batches(&self) -> u64;
+    /// Id of the record being parsed, or the last one after its end.
+    fn current_id(&self) -> i64;
+    fn coverage(&self) -> &Coverage;
+}
+
+/// Writes the records of a parser to a sink in batches.
+pub struct Loader<'a, P: RecordParser> {
+    parser: P,
+    batch: Vec<P::Record>,
+    /// The ids of the records in the batch.
+    ids: HashSet<i64>,
+    /// Size of the batch in the unit of `--batch-by`.
+    batched: usize,
+    batch_by: BatchBy,
+    batch_size: BatchSize,
+    sink: &'a mut dyn Sink,
+}
+
+impl<'a, P: RecordParser> Loader<'a, P> {
+    pub fn new(parser: P, db_opts: &DbOpt, sink: &'a mut dyn Sink) -> Self {
+        Loader {
+            parser,
+            batch: Vec::new(),
+            ids: HashSet::new(),
+            batched: 0,
+            batch_by: db_opts.batch_by,
+            batch_size: BatchSize::new(db_opts),
+            sink,
+        }
+    }
+
+    /// Add a record to the batch, a record whose id is in the batch already
+    /// is left out.
+    fn add(&mut self, record: P::Record) -> Result<()> {
+        if self.ids.insert(record.id()) {
+            self.batched += record.batch_weight(self.batch_by);
+            self.batch.push(record);
+        }
+        if self.batched >= self.batch_size.get() {
+            let started = Instant::now();
+            let batch = mem::take(&mut self.batch);
+            let records = batch.len();
+            P::Record::write(self.sink, batch)?;
+            self.batch_size.written(records, started);
+            self.clear();
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) {
+        self.batch = Vec::new();
+        self.ids = HashSet::new();
+        self.batched = 0;
+    }
+}
+
+impl<'a, P: RecordParser> Parser for Loader<'a, P> {
+    fn process(&mut self, ev: Event) -> Result<()> {
+        let root_end = matches!(&ev, Event::End(e) if e.local_name() == P::ENTITY.root());
+        if let Some(record) = self.parser.process(ev)? {
+            self.add(record)?;
+        }
+        if root_end {
+            self.finish()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        P::Record::write(self.sink, mem::take(&mut self.batch))?;
+        self.clear();
+        Ok(())
+    }
+
+    fn discard(&mut self) {
+        self.parser.discard();
+    }
+
+    fn records(&self) -> u64 {
+        self.parser.records()
+    }
+
+    fn batches(&self) -> u64 {
+        self.batch_size.batches()
+    }
+
+    fn current_id(&self) -> i64 {
+        self.parser.current_id()
+    }
+
+    fn coverage(&self) -> &Coverage {
+        self.parser.coverage()
+    }
+}
+
+/// The elements a parser came across inside its records, and whether it read
+/// them or passed them by, for `--coverage-report`. The children of ignored
+/// elements are listed as ignored too.
+pub struct Coverage {
+    enabled: bool,
+    elements: BTreeMap<String, bool>,
+}
+
+impl Coverage {
+    pub fn new(parser_opts: &ParserOpt) -> Self {
+        Coverage {
+            enabled: parser_opts.coverage_report,
+            elements: BTreeMap::new(),
+        }
+    }
+
+    pub fn saw(&mut self, name: &[u8], read: bool) {
+        if !self.enabled {
+            return;
+        }
+        *self
+            .elements
+            .entry(String::from_utf8_lossy(name).into_owned())
+            .or_insert(false) |= read;
+    }
+
+    pub fn report(&self, entity: Entity) {
+        let list = |read: bool| {
+            let names: Vec<&str> = self
+                .elements
+                .iter()
+                .filter(|(name, r)| **r == read && name.as_bytes() != entity.root())
+                .map(|(name, _)| name.as_str())
+                .collect();
+            names.join(", ")
+        };
+        info!("{} elements read: {}", entity.name(), list(true));
+        info!("{} elements ignored: {}", entity.name(), list(false));
+    }
+}
+
+/// Prints up to `limit` records of each type to stderr, for eyeballing
+/// whether a parser captures its fields.
+pub struct DebugSampler {
+    limit: usize,
+    printed: HashMap<&'static str, usize>,
+}
+
+impl DebugSampler {
+    pub fn new(limit: usize) -> Self {
+        DebugSampler {
+            limit,
+            printed: HashMap::new(),
+        }
+    }
+
+    pub fn sample<T: Debug>(&mut self, record: &T) {
+        if self.limit == 0 {
+            return;
+        }
+        let printed = self.printed.entry(std::any::type_name::<T>()).or_insert(0);
+        if *printed < self.limit {
+            eprintln!("{:?}", record);
+            *printed += 1;
+        }
+    }
+}
+
+/// Decodes text nodes, replacing invalid UTF-8 instead of failing the whole
+/// load. The reader position is kept up to date by the read loop so the bad
+/// bytes can be located in the decompressed dump.
+pub struct TextDecoder {
+    skip_errors: bool,
+    event_start: Cell<usize>,
+    position: Cell<usize>,
+    invalid: Cell<bool>,
+}
+
+impl TextDecoder {
+    pub fn new(parser_opts: &ParserOpt) -> Self {
+        TextDecoder {
+            skip_errors: parser_opts.skip_errors,
+            event_start: Cell::new(0),
+            position: Cell::new(0),
+            invalid: Cell::new(false),
+        }
+    }
+
+    /// Set the byte range of the event about to be processed.
+    pub fn set_position(&self, event_start: usize, position: usize) {
+        self.event_start.set(event_start);
+        self.position.set(position);
+    }
+
+    /// Byte offset in the decompressed dump where the current event starts.
+    pub fn event_start(&self) -> usize {
+        self.event_start.get()
+    }
+
+    pub fn decode(&self, e: &BytesText) -> Result<String> {
+        Ok(self.lossy(&e.unescaped()?))
+    }
+
+    /// The text attribute `name` of the element, decoded like text.
+    pub fn attribute(&self, e: &BytesStart, name: &[u8]) -> Result<Option<String>> {
+        for attr in e.attributes() {
+            let attr = attr?;
+            if attr.key == name {
+                return Ok(Some(self.lossy(&attr.unescaped_value()?)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Invalid UTF-8 is replaced, and marks the record for `skip_record`.
+    fn lossy(&self, text: &[u8]) -> String {
+        match str::from_utf8(text) {
+            Ok(text) => text.to_string(),
+            Err(err) => {
+                warn!(
+                    "Invalid UTF-8 in the text ending at byte {}: {}",
+                    self.position.get(),
+                    err
+                );
+                self.invalid.set(true);
+                String::from_utf8_lossy(text).into_owned()
+            }
+        }
+    }
+
+    /// Forget the invalid text of a record dropped after an error.
+    pub fn discard_record(&self) {
+        self.invalid.set(false);
+    }
+
+    /// Whether the record that just ended should be left out, because it had
+    /// invalid text and `--skip-errors` is set.
+    pub fn skip_record(&self, entity: &str, id: i64) -> bool {
+        if !self.invalid.replace(false) {
+            return false;
+        }
+        if self.skip_errors {
+            warn!("Skipping {} {} because of invalid UTF-8", entity, id);
+        } else {
+            warn!("Loading {} {} with replacement characters", entity, id);
+        }
+        self.skip_errors
+    }
+}
+
+/// What a parser counts towards the batch size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BatchBy {
+    Records,
+    ChildRows,
+}
+
+impl FromStr for BatchBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "records" => Ok(BatchBy::Records),
+            "child-rows" => Ok(BatchBy::ChildRows),
+            _ => Err(format!("unknown batch unit: {}", s)),
+        }
+    }
+}
+
+/// Number of records a parser collects before writing them, either the fixed
+/// `--batch-size` or, with `--auto-batch`, adjusted after every write so a
+/// write takes about `FLUSH_TARGET`.
+pub struct BatchSize {
+    size: usize,
+    auto: bool,
+    batches: u64,
+}
+
+impl BatchSize {
+    const FLUSH_TARGET: Duration = Duration::from_secs(1);
+    const START: usize = 1000;
+    const MIN: usize = 100;
+    const MAX: usize = 1_000_000;
+
+    pub fn new(db_opts: &DbOpt) -> Self {
+        BatchSize {
+            size: if db_opts.auto_batch {
+                Self::START
+            } else {
+                db_opts.batch_size
+            },
+            auto: db_opts.auto_batch,
+            batches: 0,
+        }
+    }
+
+    pub fn get(&self) -> usize {
+        self.size
+    }
+
+    pub fn batches(&self) -> u64 {
+        self.batches
+    }
+
+    /// Record that a batch of `records` started writing at `started` has
+    /// been written.
+    pub fn written(&mut self, records: usize, started: Instant) {
+        let elapsed = started.elapsed();
+        debug!("Wrote a batch of {} records in {:?}", records, elapsed);
+        self.batches += 1;
+        if !self.auto {
+            return;
+        }
+        let elapsed = elapsed.as_secs_f64().max(0.001);
+        // never more than double or halve at once, a single slow write
+        // should not throw the size off
+        let ratio = (Self::FLUSH_TARGET.as_secs_f64() / elapsed).clamp(0.5, 2.0);
+        self.size = ((self.size as f64 * ratio) as usize).clamp(Self::MIN, Self::MAX);
+        debug!("Next batch size: {}", self.size);
+    }
+}
+
+/// The event as the parsers read it, a CDATA section as the text it holds
+/// and no empty text. quick-xml follows a CDATA section by an empty text,
+/// which would overwrite the text of the section.
+pub fn text_event(ev: Event) -> Option<Event> {
+    match ev {
+        // quick-xml escapes the content of CDATA sections like text
+        Event::CData(e) => Some(Event::Text(e)),
+        Event::Text(e) if e.is_empty() => None,
+        ev => Some(ev),
+    }
+}
+
+/// The unescaped value of the attribute `name`, if the element has it.
+pub fn attribute(e: &BytesStart, name: &[u8]) -> Result<Option<String>> {
+    for attr in e.attributes() {
+        let attr = attr?;
+        if attr.key == name {
+            return Ok(Some(str::from_utf8(&attr.unescaped_value()?)?.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+/// The attribute `name` of the element, a record without it can't be parsed.
+pub fn required_attribute(e: &BytesStart, name: &[u8]) -> Result<String> {
+    attribute(e, name)?.ok_or_else(|| {
+        DiscogsLoadError::Value(format!(
+            "missing attribute {} of <{}>",
+            String::from_utf8_lossy(name),
+            String::from_utf8_lossy(e.local_name())
+        ))
+    })
+}
+
+/// Genres followed by styles without duplicates, for `--merge-genres-styles`.
+pub fn merge_tags(genres: &[String], styles: &[String]) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::with_capacity(genres.len() + styles.len());
+    for tag in genres.iter().chain(styles) {
+        if !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+    tags
 }
@@ -0,0 +1,87 @@
+use log::{info, warn};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::Write,
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use crate::db::{COPY_HEADER, COPY_TRAILER};
+
+/// Tees the binary COPY stream of a table into the FIFO named after the table
+/// in the `--copy-to-pipe` directory, tables without a FIFO there are skipped.
+///
+/// Every loaded file produces one complete stream per table, which can be
+/// read with e.g. `COPY release FROM STDIN BINARY`.
+#[derive(Clone)]
+pub struct Pipes {
+    dir: PathBuf,
+    open: Arc<Mutex<HashMap<String, Option<File>>>>,
+}
+
+impl Pipes {
+    pub fn new(dir: &Path) -> Self {
+        Pipes {
+            dir: dir.to_path_buf(),
+            open: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Write encoded rows to the table's FIFO. A reader that goes away only
+    /// stops the stream of that table, the load itself carries on.
+    pub fn write(&self, table: &str, rows: &[u8]) {
+        let mut open = self.open.lock().unwrap();
+        let pipe = open
+            .entry(table.to_string())
+            .or_insert_with(|| self.connect(table));
+        if let Some(file) = pipe {
+            if let Err(e) = file.write_all(rows) {
+                warn!("Stopped writing {} to its pipe: {}", table, e);
+                *pipe = None;
+            }
+        }
+    }
+
+    /// End the streams of the tables of a file, `complete` is false when the
+    /// file failed to load and the readers only see a truncated stream. The
+    /// streams of the other tables are left open, for the files loaded by
+    /// other `--jobs`.
+    pub fn close(&self, tables: &[&str], complete: bool) {
+        let mut open = self.open.lock().unwrap();
+        for table in tables {
+            if let (Some(Some(mut file)), true) = (open.remove(*table), complete) {
+                if let Err(e) = file.write_all(COPY_TRAILER) {
+                    warn!("Could not finish the pipe of {}: {}", table, e);
+                }
+            }
+        }
+    }
+
+    fn connect(&self, table: &str) -> Option<File> {
+        let path = self.dir.join(table);
+        let is_fifo = path
+            .metadata()
+            .map(|metadata| metadata.file_type().is_fifo())
+            .unwrap_or(false);
+        if !is_fifo {
+            return None;
+        }
+        info!("Waiting for a reader on {:?}", path);
+        let mut file = match OpenOptions::new().write(true).open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Could not open {:?}: {}", path, e);
+                return None;
+            }
+        };
+        match file.write_all(COPY_HEADER) {
+            Ok(()) => Some(file),
+            Err(e) => {
+                warn!("Stopped writing {} to its pipe: {}", table, e);
+                None
+            }
+        }
+    }
+}
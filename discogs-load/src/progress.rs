@@ -0,0 +1,140 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use std::{
+    io::{self, Read},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use discogs_load::parser::Entity;
+
+/// Time between two updates of the progress bar, which redraws it.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Counts the bytes read from the dump file, before decompressing them, so
+/// the progress bar follows the file whatever the number of records in it.
+pub struct CountingReader<R> {
+    inner: R,
+    read: Arc<AtomicU64>,
+}
+
+impl<R: Read> CountingReader<R> {
+    /// The reader and the counter of its bytes, which can be read from
+    /// another thread than the one reading the file.
+    pub fn new(inner: R) -> (Self, Arc<AtomicU64>) {
+        let read = Arc::new(AtomicU64::new(0));
+        let reader = CountingReader {
+            inner,
+            read: read.clone(),
+        };
+        (reader, read)
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+/// The progress of parsing a dump, a bar of the bytes read from its file of
+/// `len` bytes, or a spinner counting them when the length isn't known, like
+/// for stdin. The number of records parsed is shown next to it.
+///
+/// With `expected_records` the bar counts the records instead, and `hidden`
+/// draws nothing at all, for `--expected-records` and `--no-progress`.
+pub struct Progress {
+    bar: ProgressBar,
+    read: Arc<AtomicU64>,
+    counts_records: bool,
+    updated: Instant,
+}
+
+impl Progress {
+    pub fn new(
+        read: Arc<AtomicU64>,
+        len: Option<u64>,
+        expected_records: Option<u64>,
+        hidden: bool,
+    ) -> Self {
+        let bar = match (expected_records, len) {
+            _ if hidden => ProgressBar::hidden(),
+            (Some(records), _) => {
+                let bar = ProgressBar::new(records);
+                bar.set_style(
+                    ProgressStyle::default_bar()
+                        .template("{bar:40} {pos}/{len} {msg} {per_sec} ({eta})"),
+                );
+                bar
+            }
+            (None, Some(len)) => {
+                let bar = ProgressBar::new(len);
+                bar.set_style(
+                    ProgressStyle::default_bar()
+                        .template("{bar:40} {bytes}/{total_bytes} {bytes_per_sec} ({eta}) {msg}"),
+                );
+                bar
+            }
+            (None, None) => {
+                let spinner = ProgressBar::new_spinner();
+                spinner.set_style(
+                    ProgressStyle::default_spinner()
+                        .template("{spinner} {bytes} {bytes_per_sec} {msg}"),
+                );
+                spinner
+            }
+        };
+        Progress {
+            bar,
+            read,
+            counts_records: expected_records.is_some(),
+            updated: Instant::now(),
+        }
+    }
+
+    /// Show the bytes read and the records parsed, now and then.
+    pub fn update(&mut self, records: u64, entity: Entity) {
+        if self.updated.elapsed() >= UPDATE_INTERVAL {
+            self.draw(records, entity);
+            self.updated = Instant::now();
+        }
+    }
+
+    /// Show where the parsing stopped.
+    pub fn finish(&self, records: u64, entity: Entity) {
+        self.draw(records, entity);
+        self.bar.finish_at_current_pos();
+    }
+
+    fn draw(&self, records: u64, entity: Entity) {
+        if self.counts_records {
+            self.bar.set_position(records);
+            self.bar.set_message(format!("{}s", entity.name()));
+        } else {
+            self.bar.set_position(self.read.load(Ordering::Relaxed));
+            self.bar
+                .set_message(format!("{} {}s", records, entity.name()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counting_reader_advances_with_every_read() {
+        let data = vec![b'x'; 10_000];
+        let (mut reader, read) = CountingReader::new(&data[..]);
+        let mut buf = [0; 3000];
+        let mut counts = vec![read.load(Ordering::Relaxed)];
+        while reader.read(&mut buf).unwrap() > 0 {
+            counts.push(read.load(Ordering::Relaxed));
+        }
+        assert_eq!(counts, [0, 3000, 6000, 9000, 10_000]);
+    }
+}
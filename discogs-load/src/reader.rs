@@ -0,0 +1,106 @@
+use quick_xml::{events::Event, Reader};
+use std::{io::BufRead, rc::Rc};
+
+use crate::artist::ArtistsParser;
+use crate::error::Result;
+use crate::label::LabelsParser;
+use crate::master::MastersParser;
+use crate::parser::{text_event, ParserOpt, RecordParser, TextDecoder};
+use crate::release::ReleasesParser;
+
+/// Iterates over the records of a decompressed dump, parsed like a load
+/// parses them but without writing them anywhere. The rows of the child
+/// tables come with their record, like the labels and videos of a release.
+///
+/// The iteration ends after the first error, a gzipped dump has to be
+/// wrapped in a decoder first.
+///
+/// ```
+/// use discogs_load::ReleaseReader;
+///
+/// let xml = r#"<releases>
+///     <release id="8" status="Accepted">
+///         <title>Spreading Outward EP</title>
+///         <labels><label name="Deep4Life" catno="D4L12004" id="9" /></labels>
+///     </release>
+///     <release id="9" status="Accepted"><title>Promo</title></release>
+/// </releases>"#;
+///
+/// let releases = ReleaseReader::new(xml.as_bytes())
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// assert_eq!(releases.len(), 2);
+/// assert_eq!(releases[0].title.as_deref(), Some("Spreading Outward EP"));
+/// assert_eq!(releases[0].release_labels[0].catno, "D4L12004");
+/// assert!(releases[1].release_labels.is_empty());
+/// ```
+pub struct Records<R: BufRead, P: RecordParser> {
+    reader: Reader<R>,
+    buf: Vec<u8>,
+    parser: P,
+    text: Rc<TextDecoder>,
+    done: bool,
+}
+
+pub type ArtistReader<R> = Records<R, ArtistsParser>;
+pub type LabelReader<R> = Records<R, LabelsParser>;
+pub type MasterReader<R> = Records<R, MastersParser>;
+pub type ReleaseReader<R> = Records<R, ReleasesParser>;
+
+impl<R: BufRead, P: RecordParser> Records<R, P> {
+    pub fn new(reader: R) -> Self {
+        Self::with_options(reader, &ParserOpt::default())
+    }
+
+    /// Parse the records with options like the `--transform` rules of a load.
+    pub fn with_options(reader: R, parser_opts: &ParserOpt) -> Self {
+        let text = Rc::new(TextDecoder::new(parser_opts));
+        Records {
+            reader: Reader::from_reader(reader),
+            buf: Vec::new(),
+            parser: P::new(parser_opts, text.clone()),
+            text,
+            done: false,
+        }
+    }
+
+    /// The parser of the records, to configure it before iterating.
+    pub fn parser(&mut self) -> &mut P {
+        &mut self.parser
+    }
+}
+
+impl<R: BufRead, P: RecordParser> Iterator for Records<R, P> {
+    type Item = Result<P::Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            self.buf.clear();
+            let event_start = self.reader.buffer_position();
+            let ev = match self.reader.read_event(&mut self.buf) {
+                Ok(Event::Eof) => break,
+                Ok(ev) => ev,
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+            };
+            let ev = match text_event(ev) {
+                Some(ev) => ev,
+                None => continue,
+            };
+            self.text
+                .set_position(event_start, self.reader.buffer_position());
+            match self.parser.process(ev) {
+                Ok(Some(record)) => return Some(Ok(record)),
+                Ok(None) => {}
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        self.done = true;
+        None
+    }
+}
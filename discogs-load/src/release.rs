@@ -1,88 +1,454 @@
-use indicatif::ProgressBar;
+use log::info;
 use postgres::types::ToSql;
-use quick_xml::events::Event;
-use std::{collections::HashMap, error::Error, str};
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    io::BufRead,
+    mem,
+    rc::Rc,
+    str,
+};
 
-use crate::db::{write_releases, DbOpt, SqlSerialization};
-use crate::parser::Parser;
+use crate::db::SqlSerialization;
+use crate::error::Result;
+use crate::parser::{
+    attribute, merge_tags, required_attribute, BatchBy, Coverage, DebugSampler, Entity, MissingInt,
+    ParserOpt, Record, RecordParser, TextDecoder,
+};
+use crate::sink::{rows, Sink, TableWriter};
+use crate::transform::{apply_all, dedup_arrays, Transform, Transformable};
 
-#[derive(Clone, Debug)]
+/// The "Various Artists" artist credited on compilations.
+const VARIOUS_ARTISTS_ID: i64 = 194;
+
+/// A release of the releases dump, the values missing from the dump are
+/// `None` and stored as NULL, like the master of a release without one.
+///
+/// ```
+/// use discogs_load::ReleaseReader;
+///
+/// let xml = r#"<releases>
+///     <release id="1"><master_id is_main_release="true">5</master_id></release>
+///     <release id="2"><title>Promo</title></release>
+///     <release id="3"><master_id>0</master_id></release>
+///     <release id="4"><master_id is_main_release="true"/></release>
+///     <release id="5"><master_id is_main_release="true">0</master_id></release>
+/// </releases>"#;
+///
+/// let releases = ReleaseReader::new(xml.as_bytes())
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+/// assert_eq!(releases[0].master_id, Some(5));
+/// assert!(releases[0].is_main_release);
+/// assert_eq!(releases[1].master_id, None);
+/// assert!(!releases[1].is_main_release);
+/// assert_eq!(releases[2].master_id, None);
+/// // without a master, as an empty element or with a zero master id
+/// assert_eq!(releases[3].master_id, None);
+/// assert!(releases[3].is_main_release);
+/// assert_eq!(releases[4].master_id, None);
+/// assert!(releases[4].is_main_release);
+/// ```
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct Release {
-    pub id: i32,
-    pub status: String,
-    pub title: String,
-    pub country: String,
-    pub released: String,
-    pub notes: String,
+    pub id: i64,
+    pub status: Option<String>,
+    pub title: Option<String>,
+    pub country: Option<String>,
+    pub released: Option<String>,
+    pub notes: Option<String>,
     pub genres: Vec<String>,
     pub styles: Vec<String>,
-    pub master_id: i32,
-    pub data_quality: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    pub master_id: Option<i64>,
+    pub is_main_release: bool,
+    pub barcode: Option<String>,
+    pub matrix: Option<String>,
+    pub is_compilation: Option<bool>,
+    pub data_quality: Option<String>,
+    pub source_offset: Option<i64>,
+    pub release_artists: Vec<ReleaseArtist>,
+    pub release_extraartists: Vec<ReleaseExtraArtist>,
+    pub release_companies: Vec<ReleaseCompany>,
+    pub release_formats: Vec<ReleaseFormat>,
+    pub release_labels: Vec<ReleaseLabel>,
+    pub release_tracks: Vec<ReleaseTrack>,
+    pub release_track_extraartists: Vec<ReleaseTrackExtraArtist>,
+    pub release_identifiers: Vec<ReleaseIdentifier>,
+    pub release_videos: Vec<ReleaseVideo>,
 }
 
 impl SqlSerialization for Release {
-    fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
-        let row: Vec<&'_ (dyn ToSql + Sync)> = vec![
-            &self.id,
-            &self.status,
-            &self.title,
-            &self.country,
-            &self.released,
-            &self.notes,
-            &self.genres,
-            &self.styles,
-            &self.master_id,
-            &self.data_quality,
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let mut row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("id", &self.id),
+            ("status", &self.status),
+            ("title", &self.title),
+            ("country", &self.country),
+            ("released", &self.released),
+            ("notes", &self.notes),
         ];
+        match &self.tags {
+            Some(tags) => row.push(("tags", tags)),
+            None => {
+                row.push(("genres", &self.genres));
+                row.push(("styles", &self.styles));
+            }
+        }
+        row.push(("master_id", &self.master_id));
+        row.push(("is_main_release", &self.is_main_release));
+        row.push(("barcode", &self.barcode));
+        row.push(("matrix", &self.matrix));
+        row.push(("is_compilation", &self.is_compilation));
+        row.push(("data_quality", &self.data_quality));
+        row.push(("source_offset", &self.source_offset));
         row
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct ReleaseLabel {
-    pub release_id: i32,
+    pub release_id: i64,
     pub label: String,
     pub catno: String,
-    pub label_id: i32,
+    pub label_id: Option<i64>,
 }
 
 impl SqlSerialization for ReleaseLabel {
-    fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
-        let row: Vec<&'_ (dyn ToSql + Sync)> =
-            vec![&self.release_id, &self.label, &self.catno, &self.label_id];
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("release_id", &self.release_id),
+            ("label", &self.label),
+            ("catno", &self.catno),
+            ("label_id", &self.label_id),
+        ];
+        row
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ReleaseCompany {
+    pub release_id: i64,
+    pub company_id: i64,
+    pub name: String,
+    /// Id of what the company did, named by `entity_type_name`.
+    pub entity_type: i32,
+    pub entity_type_name: String,
+    pub catno: String,
+}
+
+impl SqlSerialization for ReleaseCompany {
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("release_id", &self.release_id),
+            ("company_id", &self.company_id),
+            ("name", &self.name),
+            ("entity_type", &self.entity_type),
+            ("entity_type_name", &self.entity_type_name),
+            ("catno", &self.catno),
+        ];
+        row
+    }
+}
+
+impl ReleaseCompany {
+    pub fn new() -> Self {
+        ReleaseCompany {
+            release_id: 0,
+            company_id: 0,
+            name: String::new(),
+            entity_type: 0,
+            entity_type_name: String::new(),
+            catno: String::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ReleaseFormat {
+    pub release_id: i64,
+    pub name: String,
+    pub qty: Option<i32>,
+    pub text: String,
+    pub descriptions: Vec<String>,
+}
+
+impl SqlSerialization for ReleaseFormat {
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("release_id", &self.release_id),
+            ("name", &self.name),
+            ("qty", &self.qty),
+            ("text", &self.text),
+            ("descriptions", &self.descriptions),
+        ];
+        row
+    }
+}
+
+impl ReleaseFormat {
+    /// The format of a `<format>` element, from its attributes.
+    fn from_element(release_id: i64, e: &BytesStart, text: &TextDecoder) -> Result<Self> {
+        Ok(ReleaseFormat {
+            release_id,
+            name: text.attribute(e, b"name")?.unwrap_or_default(),
+            qty: match attribute(e, b"qty")? {
+                Some(qty) if !qty.is_empty() => Some(str::parse(&qty)?),
+                _ => None,
+            },
+            text: text.attribute(e, b"text")?.unwrap_or_default(),
+            descriptions: Vec::new(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ReleaseTrack {
+    pub release_id: i64,
+    pub position: String,
+    pub title: String,
+    pub duration: String,
+    /// A section header like "Side A", with a title but no position or duration.
+    pub is_heading: bool,
+}
+
+impl SqlSerialization for ReleaseTrack {
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("release_id", &self.release_id),
+            ("position", &self.position),
+            ("title", &self.title),
+            ("duration", &self.duration),
+            ("is_heading", &self.is_heading),
+        ];
+        row
+    }
+}
+
+impl ReleaseTrack {
+    pub fn new() -> Self {
+        ReleaseTrack {
+            release_id: 0,
+            position: String::new(),
+            title: String::new(),
+            duration: String::new(),
+            is_heading: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ReleaseArtist {
+    pub release_id: i64,
+    /// Place of the artist in the credit, starting at 1.
+    pub position: i32,
+    pub artist_id: i64,
+    pub name: String,
+    pub anv: String,
+    /// What joins the name to the next artist, like "&" or "Feat.".
+    pub join: String,
+    pub role: String,
+}
+
+impl SqlSerialization for ReleaseArtist {
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("release_id", &self.release_id),
+            ("position", &self.position),
+            ("artist_id", &self.artist_id),
+            ("name", &self.name),
+            ("anv", &self.anv),
+            ("join_string", &self.join),
+            ("role", &self.role),
+        ];
+        row
+    }
+}
+
+impl ReleaseArtist {
+    pub fn new() -> Self {
+        ReleaseArtist {
+            release_id: 0,
+            position: 0,
+            artist_id: 0,
+            name: String::new(),
+            anv: String::new(),
+            join: String::new(),
+            role: String::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ReleaseExtraArtist {
+    pub release_id: i64,
+    pub artist_id: i64,
+    pub name: String,
+    pub anv: String,
+    pub role: String,
+}
+
+impl SqlSerialization for ReleaseExtraArtist {
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("release_id", &self.release_id),
+            ("artist_id", &self.artist_id),
+            ("name", &self.name),
+            ("anv", &self.anv),
+            ("role", &self.role),
+        ];
+        row
+    }
+}
+
+impl ReleaseExtraArtist {
+    pub fn new() -> Self {
+        ReleaseExtraArtist {
+            release_id: 0,
+            artist_id: 0,
+            name: String::new(),
+            anv: String::new(),
+            role: String::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ReleaseTrackExtraArtist {
+    pub release_id: i64,
+    pub position: String,
+    pub artist_id: i64,
+    pub name: String,
+    pub role: String,
+}
+
+impl SqlSerialization for ReleaseTrackExtraArtist {
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("release_id", &self.release_id),
+            ("position", &self.position),
+            ("artist_id", &self.artist_id),
+            ("name", &self.name),
+            ("role", &self.role),
+        ];
         row
     }
 }
 
-#[derive(Clone, Debug)]
+impl ReleaseTrackExtraArtist {
+    pub fn new() -> Self {
+        ReleaseTrackExtraArtist {
+            release_id: 0,
+            position: String::new(),
+            artist_id: 0,
+            name: String::new(),
+            role: String::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ReleaseIdentifier {
+    pub release_id: i64,
+    pub typ: String,
+    pub description: Option<String>,
+    pub value: Option<String>,
+}
+
+impl SqlSerialization for ReleaseIdentifier {
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("release_id", &self.release_id),
+            ("type", &self.typ),
+            ("description", &self.description),
+            ("value", &self.value),
+        ];
+        row
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct ReleaseVideo {
-    pub release_id: i32,
+    pub release_id: i64,
     pub duration: i32,
     pub src: String,
     pub title: String,
 }
 
+impl ReleaseVideo {
+    pub fn new() -> Self {
+        ReleaseVideo {
+            release_id: 0,
+            duration: 0,
+            src: String::new(),
+            title: String::new(),
+        }
+    }
+}
+
 impl SqlSerialization for ReleaseVideo {
-    fn to_sql(&self) -> Vec<&'_ (dyn ToSql + Sync)> {
-        let row: Vec<&'_ (dyn ToSql + Sync)> =
-            vec![&self.release_id, &self.duration, &self.src, &self.title];
+    fn to_sql(&self) -> Vec<(&'static str, &'_ (dyn ToSql + Sync))> {
+        let row: Vec<(&'static str, &'_ (dyn ToSql + Sync))> = vec![
+            ("release_id", &self.release_id),
+            ("duration", &self.duration),
+            ("src", &self.src),
+            ("title", &self.title),
+        ];
         row
     }
 }
 
+impl Transformable for Release {
+    fn text_field(&mut self, name: &str) -> Option<&mut String> {
+        match name {
+            "status" => self.status.as_mut(),
+            "title" => self.title.as_mut(),
+            "country" => self.country.as_mut(),
+            "released" => self.released.as_mut(),
+            "notes" => self.notes.as_mut(),
+            "data_quality" => self.data_quality.as_mut(),
+            _ => None,
+        }
+    }
+
+    fn text_arrays(&mut self) -> Vec<&mut Vec<String>> {
+        vec![&mut self.genres, &mut self.styles]
+    }
+}
+
 impl Release {
     pub fn new() -> Self {
         Release {
             id: 0,
-            status: String::new(),
-            title: String::new(),
-            country: String::new(),
-            released: String::new(),
-            notes: String::new(),
+            status: None,
+            title: None,
+            country: None,
+            released: None,
+            notes: None,
             genres: Vec::new(),
             styles: Vec::new(),
-            master_id: 0,
-            data_quality: String::new(),
+            tags: None,
+            master_id: None,
+            is_main_release: false,
+            barcode: None,
+            matrix: None,
+            is_compilation: None,
+            data_quality: None,
+            source_offset: None,
+            release_artists: Vec::new(),
+            release_extraartists: Vec::new(),
+            release_companies: Vec::new(),
+            release_formats: Vec::new(),
+            release_labels: Vec::new(),
+            release_tracks: Vec::new(),
+            release_track_extraartists: Vec::new(),
+            release_identifiers: Vec::new(),
+            release_videos: Vec::new(),
         }
     }
 }
@@ -101,115 +467,318 @@ enum ParserReadState {
     Style,
     MasterId,
     DataQuality,
+    // release_company
+    Companies,
+    CompanyId,
+    CompanyName,
+    CompanyEntityType,
+    CompanyEntityTypeName,
+    CompanyCatno,
+    // release_format
+    Formats,
+    FormatDescription,
+    // release_artist
+    Artists,
+    ArtistId,
+    ArtistName,
+    ArtistAnv,
+    ArtistJoin,
+    ArtistRole,
+    // release_extraartist
+    ExtraArtists,
+    ExtraArtistId,
+    ExtraArtistName,
+    ExtraArtistAnv,
+    ExtraArtistRole,
     // release_label
     Labels,
+    // release_track
+    Tracklist,
+    Track,
+    TrackPosition,
+    TrackTitle,
+    TrackDuration,
+    SubTracks,
+    // release_track_extraartist
+    TrackExtraArtists,
+    TrackArtistId,
+    TrackArtistName,
+    TrackArtistRole,
+    // release_identifier
+    Identifiers,
     // release_video
     Videos,
+    Video,
+    VideoTitle,
 }
 
-pub struct ReleasesParser<'a> {
+impl Record for Release {
+    fn id(&self) -> i64 {
+        self.id
+    }
+
+    fn batch_weight(&self, batch_by: BatchBy) -> usize {
+        match batch_by {
+            BatchBy::Records => 1,
+            BatchBy::ChildRows => {
+                self.release_artists.len()
+                    + self.release_extraartists.len()
+                    + self.release_companies.len()
+                    + self.release_formats.len()
+                    + self.release_labels.len()
+                    + self.release_tracks.len()
+                    + self.release_track_extraartists.len()
+                    + self.release_identifiers.len()
+                    + self.release_videos.len()
+            }
+        }
+    }
+
+    fn write(sink: &mut dyn Sink, batch: Vec<Self>) -> Result<()> {
+        sink.take_releases(batch)
+    }
+
+    fn write_tables(batch: &[Self], tables: &mut dyn TableWriter) -> Result<()> {
+        tables.write_rows("release", &mut rows(batch))?;
+        tables.write_rows(
+            "release_artist",
+            &mut rows(batch.iter().flat_map(|r| &r.release_artists)),
+        )?;
+        tables.write_rows(
+            "release_extraartist",
+            &mut rows(batch.iter().flat_map(|r| &r.release_extraartists)),
+        )?;
+        tables.write_rows(
+            "release_company",
+            &mut rows(batch.iter().flat_map(|r| &r.release_companies)),
+        )?;
+        tables.write_rows(
+            "release_format",
+            &mut rows(batch.iter().flat_map(|r| &r.release_formats)),
+        )?;
+        tables.write_rows(
+            "release_label",
+            &mut rows(batch.iter().flat_map(|r| &r.release_labels)),
+        )?;
+        tables.write_rows(
+            "release_track",
+            &mut rows(batch.iter().flat_map(|r| &r.release_tracks)),
+        )?;
+        tables.write_rows(
+            "release_track_extraartist",
+            &mut rows(batch.iter().flat_map(|r| &r.release_track_extraartists)),
+        )?;
+        tables.write_rows(
+            "release_identifier",
+            &mut rows(batch.iter().flat_map(|r| &r.release_identifiers)),
+        )?;
+        tables.write_rows(
+            "release_video",
+            &mut rows(batch.iter().flat_map(|r| &r.release_videos)),
+        )
+    }
+}
+
+pub struct ReleasesParser {
     state: ParserReadState,
-    releases: HashMap<i32, Release>,
     current_release: Release,
-    current_id: i32,
-    release_labels: HashMap<i32, ReleaseLabel>,
-    current_video_id: i32,
-    release_videos: HashMap<i32, ReleaseVideo>,
-    pb: ProgressBar,
-    db_opts: &'a DbOpt,
+    current_compilation: bool,
+    current_artist: ReleaseArtist,
+    current_credit: ReleaseExtraArtist,
+    current_company: ReleaseCompany,
+    current_format: Option<ReleaseFormat>,
+    current_track: ReleaseTrack,
+    current_extraartist: ReleaseTrackExtraArtist,
+    current_video: ReleaseVideo,
+    records: u64,
+    sampler: DebugSampler,
+    transforms: Vec<Transform>,
+    coverage: Coverage,
+    with_source_offset: bool,
+    dedup_arrays: bool,
+    merge_genres_styles: bool,
+    top_referenced: Option<TopReferenced>,
+    text: Rc<TextDecoder>,
+    missing_int: MissingInt,
+    with_compilation_flag: bool,
 }
 
-impl<'a> ReleasesParser<'a> {
-    pub fn new(db_opts: &'a DbOpt) -> Self {
+impl RecordParser for ReleasesParser {
+    type Record = Release;
+    const ENTITY: Entity = Entity::Release;
+
+    fn new(parser_opts: &ParserOpt, text: Rc<TextDecoder>) -> Self {
         ReleasesParser {
             state: ParserReadState::Release,
-            releases: HashMap::new(),
             current_release: Release::new(),
-            current_id: 0,
-            release_labels: HashMap::new(),
-            current_video_id: 0,
-            release_videos: HashMap::new(),
-            pb: ProgressBar::new(14976967), // https://api.discogs.com/
-            db_opts,
+            current_compilation: false,
+            current_artist: ReleaseArtist::new(),
+            current_credit: ReleaseExtraArtist::new(),
+            current_company: ReleaseCompany::new(),
+            current_format: None,
+            current_track: ReleaseTrack::new(),
+            current_extraartist: ReleaseTrackExtraArtist::new(),
+            current_video: ReleaseVideo::new(),
+            records: 0,
+            sampler: DebugSampler::new(parser_opts.debug_sample),
+            transforms: parser_opts.transforms.clone(),
+            coverage: Coverage::new(parser_opts),
+            with_source_offset: parser_opts.with_source_offset,
+            dedup_arrays: parser_opts.dedup_arrays,
+            merge_genres_styles: false,
+            top_referenced: None,
+            text,
+            missing_int: parser_opts.missing_int_as,
+            with_compilation_flag: parser_opts.with_compilation_flag,
         }
     }
+
+    fn records(&self) -> u64 {
+        self.records
+    }
+
+    fn coverage(&self) -> &Coverage {
+        &self.coverage
+    }
+
+    fn discard(&mut self) {
+        // an artist list or format cut off by an error doesn't carry over
+        self.current_artist = ReleaseArtist::new();
+        self.current_format = None;
+        self.records += 1;
+        self.state = ParserReadState::Release;
+    }
+
+    fn current_id(&self) -> i64 {
+        self.current_release.id
+    }
+
+    fn process(&mut self, ev: Event) -> Result<Option<Release>> {
+        self.read(ev)
+            .map_err(|e| e.in_record("release", self.current_release.id))
+    }
 }
 
-impl<'a> Parser<'a> for ReleasesParser<'a> {
-    fn new(&self, db_opts: &'a DbOpt) -> Self {
-        ReleasesParser {
-            state: ParserReadState::Release,
-            releases: HashMap::new(),
-            current_release: Release::new(),
-            current_id: 0,
-            release_labels: HashMap::new(),
-            current_video_id: 0,
-            release_videos: HashMap::new(),
-            pb: ProgressBar::new(14976967), // https://api.discogs.com/
-            db_opts,
+impl ReleasesParser {
+    /// Only load the releases selected by `--top-referenced`.
+    pub fn keep_only(&mut self, top_referenced: TopReferenced) {
+        self.top_referenced = Some(top_referenced);
+    }
+
+    /// Fill the `tags` of the releases instead of their genres and styles,
+    /// for `--merge-genres-styles`.
+    pub fn merge_genres_styles(&mut self) {
+        self.merge_genres_styles = true;
+    }
+
+    /// Whether the release that just ended is loaded.
+    fn selected(&mut self) -> bool {
+        match &mut self.top_referenced {
+            Some(top_referenced) => top_referenced.accept(self.current_release.master_id),
+            None => true,
+        }
+    }
+
+    /// Add the format that just ended to the release.
+    fn push_format(&mut self) {
+        if let Some(format) = self.current_format.take() {
+            self.sampler.sample(&format);
+            self.current_release.release_formats.push(format);
         }
     }
 
-    fn process(&mut self, ev: Event) -> Result<(), Box<dyn Error>> {
+    fn read(&mut self, ev: Event) -> Result<Option<Release>> {
+        let mut ended = None;
         self.state = match self.state {
             ParserReadState::Release => {
                 match ev {
                     Event::Start(e) if e.local_name() == b"release" => {
-                        self.current_release.status = str::parse(str::from_utf8(
-                            &e.attributes().nth(1).unwrap()?.unescaped_value()?,
-                        )?)?;
-                        self.current_id = str::parse(str::from_utf8(
-                            &e.attributes().next().unwrap()?.unescaped_value()?,
-                        )?)?;
-                        self.current_release.id = self.current_id;
-                        self.current_release.genres = Vec::new();
-                        self.current_release.styles = Vec::new();
+                        self.current_release = Release::new();
+                        if self.with_source_offset {
+                            self.current_release.source_offset =
+                                Some(self.text.event_start() as i64);
+                        }
+                        self.current_compilation = false;
+                        // some releases have no status, it is NULL for them
+                        self.current_release.status = self.text.attribute(&e, b"status")?;
+                        self.current_release.id = str::parse(&required_attribute(&e, b"id")?)?;
+                        // older dumps have the master as an attribute, a
+                        // `<master_id>` child overrides it
+                        if let Some(id) = attribute(&e, b"master_id")? {
+                            self.current_release.master_id = master_id(&id)?;
+                        }
                         ParserReadState::Release
                     }
 
-                    Event::Start(e) => match e.local_name() {
-                        b"title" => ParserReadState::Title,
-                        b"country" => ParserReadState::Country,
-                        b"released" => ParserReadState::Released,
-                        b"notes" => ParserReadState::Notes,
-                        b"genres" => ParserReadState::Genres,
-                        b"styles" => ParserReadState::Styles,
-                        b"master_id" => ParserReadState::MasterId,
-                        b"data_quality" => ParserReadState::DataQuality,
-                        b"labels" => ParserReadState::Labels,
-                        b"videos" => ParserReadState::Videos,
-                        _ => ParserReadState::Release,
-                    },
+                    Event::Start(e) => {
+                        let state = match e.local_name() {
+                            b"title" => ParserReadState::Title,
+                            b"country" => ParserReadState::Country,
+                            b"released" => ParserReadState::Released,
+                            b"notes" => ParserReadState::Notes,
+                            b"genres" => ParserReadState::Genres,
+                            b"styles" => ParserReadState::Styles,
+                            b"master_id" => {
+                                self.current_release.is_main_release = is_main_release(&e);
+                                ParserReadState::MasterId
+                            }
+                            b"data_quality" => ParserReadState::DataQuality,
+                            b"artists" => ParserReadState::Artists,
+                            b"extraartists" => ParserReadState::ExtraArtists,
+                            b"companies" => ParserReadState::Companies,
+                            b"formats" => ParserReadState::Formats,
+                            b"labels" => ParserReadState::Labels,
+                            b"tracklist" => ParserReadState::Tracklist,
+                            b"identifiers" => ParserReadState::Identifiers,
+                            b"videos" => ParserReadState::Videos,
+                            _ => ParserReadState::Release,
+                        };
+                        self.coverage
+                            .saw(e.local_name(), !matches!(state, ParserReadState::Release));
+                        state
+                    }
 
-                    Event::End(e) if e.local_name() == b"release" => {
-                        self.releases
-                            .entry(self.current_id)
-                            .or_insert(self.current_release.clone());
-                        if self.releases.len() >= self.db_opts.batch_size {
-                            // write to db every 1000 records and clean the hashmaps
-                            // use drain? https://doc.rust-lang.org/std/collections/struct.HashMap.html#examples-13
-                            write_releases(
-                                self.db_opts,
-                                &self.releases,
-                                &self.release_labels,
-                                &self.release_videos,
-                            )?;
-                            self.releases = HashMap::new();
-                            self.release_labels = HashMap::new();
-                            self.release_videos = HashMap::new();
-                        }
-                        self.pb.inc(1);
+                    Event::Empty(e) if e.local_name() == b"master_id" => {
+                        self.current_release.is_main_release = is_main_release(&e);
+                        ParserReadState::Release
+                    }
+
+                    Event::End(e)
+                        if e.local_name() == b"release"
+                            && (self.text.skip_record("release", self.current_release.id)
+                                || !self.selected()) =>
+                    {
+                        self.discard();
                         ParserReadState::Release
                     }
 
-                    Event::End(e) if e.local_name() == b"releases" => {
-                        // write to db remainder of releases
-                        write_releases(
-                            self.db_opts,
-                            &self.releases,
-                            &self.release_labels,
-                            &self.release_videos,
-                        )?;
+                    Event::End(e) if e.local_name() == b"release" => {
+                        apply_all(&self.transforms, &mut self.current_release);
+                        if self.dedup_arrays {
+                            dedup_arrays(&mut self.current_release);
+                        }
+                        self.current_release.master_id =
+                            self.missing_int.apply(self.current_release.master_id);
+                        if self.merge_genres_styles {
+                            self.current_release.tags = Some(merge_tags(
+                                &self.current_release.genres,
+                                &self.current_release.styles,
+                            ));
+                        }
+                        if self.with_compilation_flag {
+                            self.current_release.is_compilation = Some(self.current_compilation);
+                        }
+                        self.sampler.sample(&self.current_release);
+                        let id = self.current_release.id;
+                        // the id is kept for the checkpoint of the batch
+                        ended = Some(mem::replace(
+                            &mut self.current_release,
+                            Release {
+                                id,
+                                ..Release::new()
+                            },
+                        ));
+                        self.records += 1;
                         ParserReadState::Release
                     }
 
@@ -219,7 +788,7 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
 
             ParserReadState::Title => match ev {
                 Event::Text(e) => {
-                    self.current_release.title = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_release.title = Some(self.text.decode(&e)?);
                     ParserReadState::Title
                 }
 
@@ -230,7 +799,7 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
 
             ParserReadState::Country => match ev {
                 Event::Text(e) => {
-                    self.current_release.country = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_release.country = Some(self.text.decode(&e)?);
                     ParserReadState::Country
                 }
 
@@ -241,7 +810,7 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
 
             ParserReadState::Released => match ev {
                 Event::Text(e) => {
-                    self.current_release.released = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_release.released = Some(self.text.decode(&e)?);
                     ParserReadState::Released
                 }
 
@@ -252,7 +821,7 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
 
             ParserReadState::Notes => match ev {
                 Event::Text(e) => {
-                    self.current_release.notes = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_release.notes = Some(self.text.decode(&e)?);
                     ParserReadState::Notes
                 }
 
@@ -273,7 +842,7 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
                 Event::Text(e) => {
                     self.current_release
                         .genres
-                        .extend(str::parse(str::from_utf8(&e.unescaped()?)?));
+                        .extend(str::parse(&self.text.decode(&e)?));
                     ParserReadState::Genres
                 }
 
@@ -292,7 +861,7 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
                 Event::Text(e) => {
                     self.current_release
                         .styles
-                        .extend(str::parse(str::from_utf8(&e.unescaped()?)?));
+                        .extend(str::parse(&self.text.decode(&e)?));
                     ParserReadState::Styles
                 }
 
@@ -301,7 +870,7 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
 
             ParserReadState::MasterId => match ev {
                 Event::Text(e) => {
-                    self.current_release.master_id = str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_release.master_id = master_id(&self.text.decode(&e)?)?;
                     ParserReadState::MasterId
                 }
 
@@ -312,8 +881,7 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
 
             ParserReadState::DataQuality => match ev {
                 Event::Text(e) => {
-                    self.current_release.data_quality =
-                        str::parse(str::from_utf8(&e.unescaped()?)?)?;
+                    self.current_release.data_quality = Some(self.text.decode(&e)?);
                     ParserReadState::DataQuality
                 }
 
@@ -322,23 +890,332 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
                 _ => ParserReadState::DataQuality,
             },
 
+            ParserReadState::Artists => match ev {
+                Event::Start(e) => match e.local_name() {
+                    b"artist" => {
+                        let position = self.current_artist.position + 1;
+                        self.current_artist = ReleaseArtist::new();
+                        self.current_artist.release_id = self.current_release.id;
+                        self.current_artist.position = position;
+                        ParserReadState::Artists
+                    }
+                    b"id" => ParserReadState::ArtistId,
+                    b"name" => ParserReadState::ArtistName,
+                    b"anv" => ParserReadState::ArtistAnv,
+                    b"join" => ParserReadState::ArtistJoin,
+                    b"role" => ParserReadState::ArtistRole,
+                    _ => ParserReadState::Artists,
+                },
+
+                Event::End(e) => match e.local_name() {
+                    b"artist" => {
+                        self.sampler.sample(&self.current_artist);
+                        // the next artist counts on from the position
+                        let position = self.current_artist.position;
+                        let artist = mem::replace(
+                            &mut self.current_artist,
+                            ReleaseArtist {
+                                position,
+                                ..ReleaseArtist::new()
+                            },
+                        );
+                        self.current_release.release_artists.push(artist);
+                        ParserReadState::Artists
+                    }
+                    b"artists" => {
+                        self.current_artist = ReleaseArtist::new();
+                        ParserReadState::Release
+                    }
+                    _ => ParserReadState::Artists,
+                },
+
+                _ => ParserReadState::Artists,
+            },
+
+            ParserReadState::ArtistId => match ev {
+                Event::Text(e) => {
+                    self.current_artist.artist_id = str::parse(&self.text.decode(&e)?)?;
+                    self.current_compilation |= self.current_artist.artist_id == VARIOUS_ARTISTS_ID;
+                    ParserReadState::ArtistId
+                }
+
+                Event::End(e) if e.local_name() == b"id" => ParserReadState::Artists,
+
+                _ => ParserReadState::ArtistId,
+            },
+
+            ParserReadState::ArtistName => match ev {
+                Event::Text(e) => {
+                    self.current_artist.name = self.text.decode(&e)?;
+                    ParserReadState::ArtistName
+                }
+
+                Event::End(e) if e.local_name() == b"name" => ParserReadState::Artists,
+
+                _ => ParserReadState::ArtistName,
+            },
+
+            ParserReadState::ArtistAnv => match ev {
+                Event::Text(e) => {
+                    self.current_artist.anv = self.text.decode(&e)?;
+                    ParserReadState::ArtistAnv
+                }
+
+                Event::End(e) if e.local_name() == b"anv" => ParserReadState::Artists,
+
+                _ => ParserReadState::ArtistAnv,
+            },
+
+            ParserReadState::ArtistJoin => match ev {
+                Event::Text(e) => {
+                    self.current_artist.join = self.text.decode(&e)?;
+                    ParserReadState::ArtistJoin
+                }
+
+                Event::End(e) if e.local_name() == b"join" => ParserReadState::Artists,
+
+                _ => ParserReadState::ArtistJoin,
+            },
+
+            ParserReadState::ArtistRole => match ev {
+                Event::Text(e) => {
+                    self.current_artist.role = self.text.decode(&e)?;
+                    ParserReadState::ArtistRole
+                }
+
+                Event::End(e) if e.local_name() == b"role" => ParserReadState::Artists,
+
+                _ => ParserReadState::ArtistRole,
+            },
+
+            ParserReadState::ExtraArtists => match ev {
+                Event::Start(e) => match e.local_name() {
+                    b"artist" => {
+                        self.current_credit = ReleaseExtraArtist::new();
+                        self.current_credit.release_id = self.current_release.id;
+                        ParserReadState::ExtraArtists
+                    }
+                    b"id" => ParserReadState::ExtraArtistId,
+                    b"name" => ParserReadState::ExtraArtistName,
+                    b"anv" => ParserReadState::ExtraArtistAnv,
+                    b"role" => ParserReadState::ExtraArtistRole,
+                    _ => ParserReadState::ExtraArtists,
+                },
+
+                Event::End(e) => match e.local_name() {
+                    b"artist" => {
+                        self.sampler.sample(&self.current_credit);
+                        self.current_release.release_extraartists.push(mem::replace(
+                            &mut self.current_credit,
+                            ReleaseExtraArtist::new(),
+                        ));
+                        ParserReadState::ExtraArtists
+                    }
+                    b"extraartists" => ParserReadState::Release,
+                    _ => ParserReadState::ExtraArtists,
+                },
+
+                _ => ParserReadState::ExtraArtists,
+            },
+
+            ParserReadState::ExtraArtistId => match ev {
+                Event::Text(e) => {
+                    self.current_credit.artist_id = str::parse(&self.text.decode(&e)?)?;
+                    ParserReadState::ExtraArtistId
+                }
+
+                Event::End(e) if e.local_name() == b"id" => ParserReadState::ExtraArtists,
+
+                _ => ParserReadState::ExtraArtistId,
+            },
+
+            ParserReadState::ExtraArtistName => match ev {
+                Event::Text(e) => {
+                    self.current_credit.name = self.text.decode(&e)?;
+                    ParserReadState::ExtraArtistName
+                }
+
+                Event::End(e) if e.local_name() == b"name" => ParserReadState::ExtraArtists,
+
+                _ => ParserReadState::ExtraArtistName,
+            },
+
+            ParserReadState::ExtraArtistAnv => match ev {
+                Event::Text(e) => {
+                    self.current_credit.anv = self.text.decode(&e)?;
+                    ParserReadState::ExtraArtistAnv
+                }
+
+                Event::End(e) if e.local_name() == b"anv" => ParserReadState::ExtraArtists,
+
+                _ => ParserReadState::ExtraArtistAnv,
+            },
+
+            // like on tracks, the role is kept verbatim
+            ParserReadState::ExtraArtistRole => match ev {
+                Event::Text(e) => {
+                    self.current_credit.role = self.text.decode(&e)?;
+                    ParserReadState::ExtraArtistRole
+                }
+
+                Event::End(e) if e.local_name() == b"role" => ParserReadState::ExtraArtists,
+
+                _ => ParserReadState::ExtraArtistRole,
+            },
+
+            ParserReadState::Companies => match ev {
+                Event::Start(e) => match e.local_name() {
+                    b"company" => {
+                        self.current_company = ReleaseCompany::new();
+                        self.current_company.release_id = self.current_release.id;
+                        ParserReadState::Companies
+                    }
+                    b"id" => ParserReadState::CompanyId,
+                    b"name" => ParserReadState::CompanyName,
+                    b"entity_type" => ParserReadState::CompanyEntityType,
+                    b"entity_type_name" => ParserReadState::CompanyEntityTypeName,
+                    b"catno" => ParserReadState::CompanyCatno,
+                    _ => ParserReadState::Companies,
+                },
+
+                Event::End(e) => match e.local_name() {
+                    b"company" => {
+                        self.sampler.sample(&self.current_company);
+                        self.current_release.release_companies.push(mem::replace(
+                            &mut self.current_company,
+                            ReleaseCompany::new(),
+                        ));
+                        ParserReadState::Companies
+                    }
+                    b"companies" => ParserReadState::Release,
+                    _ => ParserReadState::Companies,
+                },
+
+                _ => ParserReadState::Companies,
+            },
+
+            ParserReadState::CompanyId => match ev {
+                Event::Text(e) => {
+                    self.current_company.company_id = str::parse(&self.text.decode(&e)?)?;
+                    ParserReadState::CompanyId
+                }
+
+                Event::End(e) if e.local_name() == b"id" => ParserReadState::Companies,
+
+                _ => ParserReadState::CompanyId,
+            },
+
+            ParserReadState::CompanyName => match ev {
+                Event::Text(e) => {
+                    self.current_company.name = self.text.decode(&e)?;
+                    ParserReadState::CompanyName
+                }
+
+                Event::End(e) if e.local_name() == b"name" => ParserReadState::Companies,
+
+                _ => ParserReadState::CompanyName,
+            },
+
+            ParserReadState::CompanyEntityType => match ev {
+                Event::Text(e) => {
+                    self.current_company.entity_type = str::parse(&self.text.decode(&e)?)?;
+                    ParserReadState::CompanyEntityType
+                }
+
+                Event::End(e) if e.local_name() == b"entity_type" => ParserReadState::Companies,
+
+                _ => ParserReadState::CompanyEntityType,
+            },
+
+            ParserReadState::CompanyEntityTypeName => match ev {
+                Event::Text(e) => {
+                    self.current_company.entity_type_name = self.text.decode(&e)?;
+                    ParserReadState::CompanyEntityTypeName
+                }
+
+                Event::End(e) if e.local_name() == b"entity_type_name" => {
+                    ParserReadState::Companies
+                }
+
+                _ => ParserReadState::CompanyEntityTypeName,
+            },
+
+            ParserReadState::CompanyCatno => match ev {
+                Event::Text(e) => {
+                    self.current_company.catno = self.text.decode(&e)?;
+                    ParserReadState::CompanyCatno
+                }
+
+                Event::End(e) if e.local_name() == b"catno" => ParserReadState::Companies,
+
+                _ => ParserReadState::CompanyCatno,
+            },
+
+            ParserReadState::Formats => match ev {
+                Event::Start(e) if e.local_name() == b"format" => {
+                    self.current_format = Some(ReleaseFormat::from_element(
+                        self.current_release.id,
+                        &e,
+                        &self.text,
+                    )?);
+                    ParserReadState::Formats
+                }
+
+                // a format without descriptions
+                Event::Empty(e) if e.local_name() == b"format" => {
+                    self.current_format = Some(ReleaseFormat::from_element(
+                        self.current_release.id,
+                        &e,
+                        &self.text,
+                    )?);
+                    self.push_format();
+                    ParserReadState::Formats
+                }
+
+                Event::Start(e) if e.local_name() == b"description" => {
+                    ParserReadState::FormatDescription
+                }
+
+                Event::End(e) if e.local_name() == b"format" => {
+                    self.push_format();
+                    ParserReadState::Formats
+                }
+
+                Event::End(e) if e.local_name() == b"formats" => ParserReadState::Release,
+
+                _ => ParserReadState::Formats,
+            },
+
+            ParserReadState::FormatDescription => match ev {
+                Event::Text(e) => {
+                    let description = self.text.decode(&e)?;
+                    self.current_compilation |= description == "Compilation";
+                    if let Some(format) = &mut self.current_format {
+                        format.descriptions.push(description);
+                    }
+                    ParserReadState::FormatDescription
+                }
+
+                Event::End(e) if e.local_name() == b"description" => ParserReadState::Formats,
+
+                _ => ParserReadState::FormatDescription,
+            },
+
             ParserReadState::Labels => match ev {
                 Event::Empty(e) => {
-                    let label_id = str::parse(str::from_utf8(
-                        &e.attributes().nth(2).unwrap()?.unescaped_value()?,
-                    )?)?;
-                    self.release_labels.entry(label_id).or_insert(ReleaseLabel {
+                    let label_id = match attribute(&e, b"id")? {
+                        Some(id) => Some(str::parse(&id)?),
+                        None => None,
+                    };
+                    let label_id = self.missing_int.apply(label_id);
+                    let release_label = ReleaseLabel {
                         release_id: self.current_release.id,
-                        label: str::parse(str::from_utf8(
-                            &e.attributes().next().unwrap()?.unescaped_value()?,
-                        )?)?,
-                        catno: str::parse(str::from_utf8(
-                            &e.attributes().nth(1).unwrap()?.unescaped_value()?,
-                        )?)?,
-                        label_id: str::parse(str::from_utf8(
-                            &e.attributes().nth(2).unwrap()?.unescaped_value()?,
-                        )?)?,
-                    });
+                        label: self.text.attribute(&e, b"name")?.unwrap_or_default(),
+                        catno: self.text.attribute(&e, b"catno")?.unwrap_or_default(),
+                        label_id,
+                    };
+                    self.sampler.sample(&release_label);
+                    self.current_release.release_labels.push(release_label);
                     ParserReadState::Labels
                 }
 
@@ -347,30 +1224,311 @@ impl<'a> Parser<'a> for ReleasesParser<'a> {
                 _ => ParserReadState::Labels,
             },
 
+            ParserReadState::Tracklist => match ev {
+                Event::Start(e) if e.local_name() == b"track" => {
+                    self.current_track = ReleaseTrack::new();
+                    self.current_track.release_id = self.current_release.id;
+                    ParserReadState::Track
+                }
+
+                Event::End(e) if e.local_name() == b"tracklist" => ParserReadState::Release,
+
+                _ => ParserReadState::Tracklist,
+            },
+
+            ParserReadState::Track => match ev {
+                Event::Start(e) => match e.local_name() {
+                    b"position" => ParserReadState::TrackPosition,
+                    b"title" => ParserReadState::TrackTitle,
+                    b"duration" => ParserReadState::TrackDuration,
+                    b"extraartists" => ParserReadState::TrackExtraArtists,
+                    b"sub_tracks" => ParserReadState::SubTracks,
+                    _ => ParserReadState::Track,
+                },
+
+                Event::End(e) if e.local_name() == b"track" => {
+                    let track = &mut self.current_track;
+                    track.is_heading = !track.title.is_empty()
+                        && track.position.is_empty()
+                        && track.duration.is_empty();
+                    self.sampler.sample(&self.current_track);
+                    self.current_release
+                        .release_tracks
+                        .push(mem::replace(&mut self.current_track, ReleaseTrack::new()));
+                    ParserReadState::Tracklist
+                }
+
+                _ => ParserReadState::Track,
+            },
+
+            ParserReadState::TrackPosition => match ev {
+                Event::Text(e) => {
+                    self.current_track.position = str::parse(&self.text.decode(&e)?)?;
+                    ParserReadState::TrackPosition
+                }
+
+                Event::End(e) if e.local_name() == b"position" => ParserReadState::Track,
+
+                _ => ParserReadState::TrackPosition,
+            },
+
+            ParserReadState::TrackTitle => match ev {
+                Event::Text(e) => {
+                    self.current_track.title = str::parse(&self.text.decode(&e)?)?;
+                    ParserReadState::TrackTitle
+                }
+
+                Event::End(e) if e.local_name() == b"title" => ParserReadState::Track,
+
+                _ => ParserReadState::TrackTitle,
+            },
+
+            ParserReadState::TrackDuration => match ev {
+                Event::Text(e) => {
+                    self.current_track.duration = str::parse(&self.text.decode(&e)?)?;
+                    ParserReadState::TrackDuration
+                }
+
+                Event::End(e) if e.local_name() == b"duration" => ParserReadState::Track,
+
+                _ => ParserReadState::TrackDuration,
+            },
+
+            // the tracks of an index track are not loaded
+            ParserReadState::SubTracks => match ev {
+                Event::End(e) if e.local_name() == b"sub_tracks" => ParserReadState::Track,
+
+                _ => ParserReadState::SubTracks,
+            },
+
+            ParserReadState::TrackExtraArtists => match ev {
+                Event::Start(e) => match e.local_name() {
+                    b"artist" => {
+                        self.current_extraartist = ReleaseTrackExtraArtist::new();
+                        self.current_extraartist.release_id = self.current_release.id;
+                        self.current_extraartist.position = self.current_track.position.clone();
+                        ParserReadState::TrackExtraArtists
+                    }
+                    b"id" => ParserReadState::TrackArtistId,
+                    b"name" => ParserReadState::TrackArtistName,
+                    b"role" => ParserReadState::TrackArtistRole,
+                    _ => ParserReadState::TrackExtraArtists,
+                },
+
+                Event::End(e) => match e.local_name() {
+                    b"artist" => {
+                        self.sampler.sample(&self.current_extraartist);
+                        self.current_release
+                            .release_track_extraartists
+                            .push(mem::replace(
+                                &mut self.current_extraartist,
+                                ReleaseTrackExtraArtist::new(),
+                            ));
+                        ParserReadState::TrackExtraArtists
+                    }
+                    b"extraartists" => ParserReadState::Track,
+                    _ => ParserReadState::TrackExtraArtists,
+                },
+
+                _ => ParserReadState::TrackExtraArtists,
+            },
+
+            ParserReadState::TrackArtistId => match ev {
+                Event::Text(e) => {
+                    self.current_extraartist.artist_id = str::parse(&self.text.decode(&e)?)?;
+                    ParserReadState::TrackArtistId
+                }
+
+                Event::End(e) if e.local_name() == b"id" => ParserReadState::TrackExtraArtists,
+
+                _ => ParserReadState::TrackArtistId,
+            },
+
+            ParserReadState::TrackArtistName => match ev {
+                Event::Text(e) => {
+                    self.current_extraartist.name = str::parse(&self.text.decode(&e)?)?;
+                    ParserReadState::TrackArtistName
+                }
+
+                Event::End(e) if e.local_name() == b"name" => ParserReadState::TrackExtraArtists,
+
+                _ => ParserReadState::TrackArtistName,
+            },
+
+            // the role is kept verbatim, including any bracketed details
+            ParserReadState::TrackArtistRole => match ev {
+                Event::Text(e) => {
+                    self.current_extraartist.role = str::parse(&self.text.decode(&e)?)?;
+                    ParserReadState::TrackArtistRole
+                }
+
+                Event::End(e) if e.local_name() == b"role" => ParserReadState::TrackExtraArtists,
+
+                _ => ParserReadState::TrackArtistRole,
+            },
+
+            ParserReadState::Identifiers => match ev {
+                Event::Empty(e) if e.local_name() == b"identifier" => {
+                    let release_identifier = ReleaseIdentifier {
+                        release_id: self.current_release.id,
+                        typ: self.text.attribute(&e, b"type")?.unwrap_or_default(),
+                        description: self.text.attribute(&e, b"description")?,
+                        value: self.text.attribute(&e, b"value")?,
+                    };
+                    let first = match release_identifier.typ.as_str() {
+                        "Barcode" => &mut self.current_release.barcode,
+                        "Matrix / Runout" => &mut self.current_release.matrix,
+                        _ => &mut None,
+                    };
+                    if first.is_none() {
+                        *first = release_identifier.value.clone();
+                    }
+                    self.sampler.sample(&release_identifier);
+                    self.current_release
+                        .release_identifiers
+                        .push(release_identifier);
+                    ParserReadState::Identifiers
+                }
+
+                Event::End(e) if e.local_name() == b"identifiers" => ParserReadState::Release,
+
+                _ => ParserReadState::Identifiers,
+            },
+
             ParserReadState::Videos => match ev {
                 Event::Start(e) if e.local_name() == b"video" => {
-                    self.release_videos
-                        .entry(self.current_video_id)
-                        .or_insert(ReleaseVideo {
-                            release_id: self.current_release.id,
-                            duration: str::parse(str::from_utf8(
-                                &e.attributes().nth(1).unwrap()?.unescaped_value()?,
-                            )?)?,
-                            src: str::parse(str::from_utf8(
-                                &e.attributes().next().unwrap()?.unescaped_value()?,
-                            )?)?,
-                            title: String::new(),
-                        });
-                    self.current_video_id += 1;
-                    ParserReadState::Videos
+                    self.current_video = ReleaseVideo {
+                        release_id: self.current_release.id,
+                        duration: match attribute(&e, b"duration")? {
+                            Some(duration) => str::parse(&duration)?,
+                            None => 0,
+                        },
+                        src: self.text.attribute(&e, b"src")?.unwrap_or_default(),
+                        title: String::new(),
+                    };
+                    ParserReadState::Video
                 }
 
                 Event::End(e) if e.local_name() == b"videos" => ParserReadState::Release,
 
                 _ => ParserReadState::Videos,
             },
+
+            ParserReadState::Video => match ev {
+                Event::Start(e) if e.local_name() == b"title" => ParserReadState::VideoTitle,
+
+                // videos without a title keep an empty one
+                Event::End(e) if e.local_name() == b"video" => {
+                    self.sampler.sample(&self.current_video);
+                    self.current_release
+                        .release_videos
+                        .push(mem::replace(&mut self.current_video, ReleaseVideo::new()));
+                    ParserReadState::Videos
+                }
+
+                _ => ParserReadState::Video,
+            },
+
+            ParserReadState::VideoTitle => match ev {
+                Event::Text(e) => {
+                    self.current_video.title = self.text.decode(&e)?;
+                    ParserReadState::VideoTitle
+                }
+
+                Event::End(e) if e.local_name() == b"title" => ParserReadState::Video,
+
+                _ => ParserReadState::VideoTitle,
+            },
         };
 
-        Ok(())
+        Ok(ended)
+    }
+}
+
+/// The releases of the masters with the most releases, for `--top-referenced`.
+pub struct TopReferenced {
+    masters: HashSet<i64>,
+    /// The masters have fewer releases than requested, any release is taken.
+    fill: bool,
+    remaining: usize,
+}
+
+impl TopReferenced {
+    /// Count the releases of every master in a first pass over the dump and
+    /// select the masters with the most releases until there are `n`.
+    pub fn count<B: BufRead>(reader: &mut Reader<B>, n: usize) -> Result<Self> {
+        info!("Counting the releases per master");
+        let mut releases_per_master: HashMap<i64, usize> = HashMap::new();
+        let mut buf = Vec::new();
+        let mut in_master_id = false;
+        let mut master_id: Option<i64> = None;
+        loop {
+            match reader.read_event(&mut buf)? {
+                Event::Start(e) if e.local_name() == b"release" => {
+                    master_id = attribute(&e, b"master_id")?
+                        .map(|id| str::parse(&id))
+                        .transpose()?;
+                }
+                Event::Start(e) if e.local_name() == b"master_id" => in_master_id = true,
+                Event::Text(e) if in_master_id => {
+                    master_id = Some(str::parse(str::from_utf8(&e.unescaped()?)?)?);
+                }
+                Event::End(e) if e.local_name() == b"master_id" => in_master_id = false,
+                Event::End(e) if e.local_name() == b"release" => {
+                    if let Some(master_id) = master_id.take() {
+                        *releases_per_master.entry(master_id).or_insert(0) += 1;
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+            buf.clear();
+        }
+
+        let mut masters: Vec<(i64, usize)> = releases_per_master.into_iter().collect();
+        masters.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        let mut selected = HashSet::new();
+        let mut releases = 0;
+        for (master_id, count) in masters {
+            if releases >= n {
+                break;
+            }
+            selected.insert(master_id);
+            releases += count;
+        }
+        info!(
+            "Loading up to {} releases of the {} masters with the most releases",
+            n,
+            selected.len()
+        );
+        Ok(TopReferenced {
+            masters: selected,
+            fill: releases < n,
+            remaining: n,
+        })
     }
+
+    fn accept(&mut self, master_id: Option<i64>) -> bool {
+        let selected =
+            self.fill || matches!(master_id, Some(master_id) if self.masters.contains(&master_id));
+        if self.remaining == 0 || !selected {
+            return false;
+        }
+        self.remaining -= 1;
+        true
+    }
+}
+
+/// The master of a release, 0 isn't a master and is missing like an absent
+/// `<master_id>`, stored as `--missing-int-as` says.
+fn master_id(text: &str) -> Result<Option<i64>> {
+    let id: i64 = str::parse(text)?;
+    Ok(Some(id).filter(|id| *id != 0))
+}
+
+/// Whether a `<master_id>` element carries `is_main_release="true"`.
+fn is_main_release(e: &BytesStart) -> bool {
+    e.attributes()
+        .filter_map(|a| a.ok())
+        .any(|a| a.key == b"is_main_release" && &*a.value == b"true")
 }
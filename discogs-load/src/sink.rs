@@ -0,0 +1,440 @@
+use std::{
+    collections::BTreeMap,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{sync_channel, SyncSender},
+        Arc, Mutex,
+    },
+    thread::{Scope, ScopedJoinHandle},
+};
+
+use crate::artist::Artist;
+use crate::db::SqlSerialization;
+use crate::error::{DiscogsLoadError, Result};
+use crate::label::Label;
+use crate::master::Master;
+use crate::parser::Record;
+use crate::release::Release;
+
+/// Where a loader writes its batches of records, with the rows of their child
+/// tables, like `db::PostgresSink` writing them to the database.
+pub trait Sink {
+    fn write_artists(&mut self, batch: &[Artist]) -> Result<()>;
+    fn write_labels(&mut self, batch: &[Label]) -> Result<()>;
+    fn write_masters(&mut self, batch: &[Master]) -> Result<()>;
+    fn write_releases(&mut self, batch: &[Release]) -> Result<()>;
+
+    /// Write a batch the loader is done with, a sink keeping the batch, like
+    /// `ThreadedSink` handing it to a writer thread, takes it without a copy.
+    fn take_artists(&mut self, batch: Vec<Artist>) -> Result<()> {
+        self.write_artists(&batch)
+    }
+
+    fn take_labels(&mut self, batch: Vec<Label>) -> Result<()> {
+        self.write_labels(&batch)
+    }
+
+    fn take_masters(&mut self, batch: Vec<Master>) -> Result<()> {
+        self.write_masters(&batch)
+    }
+
+    fn take_releases(&mut self, batch: Vec<Release>) -> Result<()> {
+        self.write_releases(&batch)
+    }
+
+    /// Write out what the sink buffered, after every file.
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Complete what the sink wrote after the last file, like the footers of
+    /// Parquet files.
+    fn finish(&mut self) -> Result<()> {
+        self.flush()
+    }
+
+    /// Number of rows written per table so far, by the sinks counting them.
+    fn row_counts(&self) -> BTreeMap<String, u64> {
+        BTreeMap::new()
+    }
+}
+
+/// The database the records are loaded into, for `--backend`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Postgres,
+    Sqlite,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "postgres" => Ok(Backend::Postgres),
+            "sqlite" => Ok(Backend::Sqlite),
+            _ => Err(format!("unknown backend: {}", s)),
+        }
+    }
+}
+
+/// Where the records go, for `--output-format`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Postgres,
+    Csv,
+    Jsonl,
+    Parquet,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "postgres" => Ok(OutputFormat::Postgres),
+            "csv" => Ok(OutputFormat::Csv),
+            "jsonl" => Ok(OutputFormat::Jsonl),
+            "parquet" => Ok(OutputFormat::Parquet),
+            _ => Err(format!("unknown output format: {}", s)),
+        }
+    }
+}
+
+/// Writes the rows of a batch table by table, for the sinks keeping every
+/// table on its own like the database does. `Record::write_tables` gives
+/// the rows of every table of a batch to it.
+pub trait TableWriter {
+    fn write_rows(
+        &mut self,
+        table: &'static str,
+        rows: &mut dyn Iterator<Item = &dyn SqlSerialization>,
+    ) -> Result<()>;
+}
+
+/// The rows of a table as `TableWriter::write_rows` takes them.
+pub fn rows<'r, T: SqlSerialization + 'r>(
+    rows: impl IntoIterator<Item = &'r T>,
+) -> impl Iterator<Item = &'r dyn SqlSerialization> {
+    rows.into_iter().map(|row| row as &dyn SqlSerialization)
+}
+
+/// Keeps the batches written to it, to look at what a loader writes without
+/// a database.
+///
+/// ```
+/// use discogs_load::parser::{Loader, Parser, ParserOpt, RecordParser, TextDecoder};
+/// use discogs_load::{db::DbOpt, release::ReleasesParser, sink::VecSink};
+/// use quick_xml::{events::Event, Reader};
+/// use std::rc::Rc;
+/// use structopt::StructOpt;
+///
+/// let xml = r#"<releases>
+///     <release id="8"><title>Spreading Outward EP</title></release>
+///     <release id="9"><title>Promo</title></release>
+///     <release id="10"><title>Tide</title></release>
+/// </releases>"#;
+/// let db_opts = DbOpt::from_iter(&["discogs-load", "--batch-size", "2"]);
+/// let parser_opts = ParserOpt::default();
+/// let parser = ReleasesParser::new(&parser_opts, Rc::new(TextDecoder::new(&parser_opts)));
+/// let mut sink = VecSink::default();
+///
+/// let mut loader = Loader::new(parser, &db_opts, &mut sink);
+/// let mut reader = Reader::from_str(xml);
+/// let mut buf = Vec::new();
+/// loop {
+///     match reader.read_event(&mut buf).unwrap() {
+///         Event::Eof => break,
+///         ev => loader.process(ev).unwrap(),
+///     }
+///     buf.clear();
+/// }
+/// drop(loader);
+///
+/// let batches: Vec<usize> = sink.releases.iter().map(|batch| batch.len()).collect();
+/// assert_eq!(batches, [2, 1]);
+/// assert_eq!(sink.releases[1][0].title.as_deref(), Some("Tide"));
+/// ```
+#[derive(Debug, Default)]
+pub struct VecSink {
+    pub artists: Vec<Vec<Artist>>,
+    pub labels: Vec<Vec<Label>>,
+    pub masters: Vec<Vec<Master>>,
+    pub releases: Vec<Vec<Release>>,
+}
+
+impl Sink for VecSink {
+    fn write_artists(&mut self, batch: &[Artist]) -> Result<()> {
+        self.artists.push(batch.to_vec());
+        Ok(())
+    }
+
+    fn write_labels(&mut self, batch: &[Label]) -> Result<()> {
+        self.labels.push(batch.to_vec());
+        Ok(())
+    }
+
+    fn write_masters(&mut self, batch: &[Master]) -> Result<()> {
+        self.masters.push(batch.to_vec());
+        Ok(())
+    }
+
+    fn write_releases(&mut self, batch: &[Release]) -> Result<()> {
+        self.releases.push(batch.to_vec());
+        Ok(())
+    }
+
+    fn take_artists(&mut self, batch: Vec<Artist>) -> Result<()> {
+        self.artists.push(batch);
+        Ok(())
+    }
+
+    fn take_labels(&mut self, batch: Vec<Label>) -> Result<()> {
+        self.labels.push(batch);
+        Ok(())
+    }
+
+    fn take_masters(&mut self, batch: Vec<Master>) -> Result<()> {
+        self.masters.push(batch);
+        Ok(())
+    }
+
+    fn take_releases(&mut self, batch: Vec<Release>) -> Result<()> {
+        self.releases.push(batch);
+        Ok(())
+    }
+}
+
+/// Counts the rows the batches would add to every table without writing
+/// them anywhere, for `--dry-run`.
+#[derive(Debug, Default)]
+pub struct CountingSink {
+    rows: BTreeMap<String, u64>,
+}
+
+impl TableWriter for CountingSink {
+    fn write_rows(
+        &mut self,
+        table: &'static str,
+        rows: &mut dyn Iterator<Item = &dyn SqlSerialization>,
+    ) -> Result<()> {
+        *self.rows.entry(table.to_string()).or_insert(0) += rows.count() as u64;
+        Ok(())
+    }
+}
+
+impl Sink for CountingSink {
+    fn write_artists(&mut self, batch: &[Artist]) -> Result<()> {
+        Artist::write_tables(batch, self)
+    }
+
+    fn write_labels(&mut self, batch: &[Label]) -> Result<()> {
+        Label::write_tables(batch, self)
+    }
+
+    fn write_masters(&mut self, batch: &[Master]) -> Result<()> {
+        Master::write_tables(batch, self)
+    }
+
+    fn write_releases(&mut self, batch: &[Release]) -> Result<()> {
+        Release::write_tables(batch, self)
+    }
+
+    fn row_counts(&self) -> BTreeMap<String, u64> {
+        self.rows.clone()
+    }
+}
+
+/// A batch on its way to a writer thread.
+enum Batch {
+    Artists(Vec<Artist>),
+    Labels(Vec<Label>),
+    Masters(Vec<Master>),
+    Releases(Vec<Release>),
+}
+
+impl Batch {
+    fn write(&self, sink: &mut dyn Sink) -> Result<()> {
+        match self {
+            Batch::Artists(batch) => sink.write_artists(batch),
+            Batch::Labels(batch) => sink.write_labels(batch),
+            Batch::Masters(batch) => sink.write_masters(batch),
+            Batch::Releases(batch) => sink.write_releases(batch),
+        }
+    }
+}
+
+/// Hands the batches to writer threads, each writing them to a sink of its
+/// own, so parsing goes on while the batches before are written, for
+/// `--writer-threads`.
+///
+/// As many batches as there are writers wait for one, a parser getting ahead
+/// of the writers waits for them to take the next batch. The batches are
+/// written in the order they were sent by a single writer, several write
+/// them side by side.
+///
+/// ```
+/// use discogs_load::sink::{CountingSink, Sink, ThreadedSink};
+/// use discogs_load::{Artist, Label, Master, Release, ReleaseReader, Result};
+/// use std::{fs::File, io::BufReader, sync::{Arc, Mutex}, thread};
+///
+/// /// The writers count the rows of their batches together.
+/// struct Shared(Arc<Mutex<CountingSink>>);
+///
+/// impl Sink for Shared {
+///     fn write_artists(&mut self, batch: &[Artist]) -> Result<()> {
+///         self.0.lock().unwrap().write_artists(batch)
+///     }
+///     fn write_labels(&mut self, batch: &[Label]) -> Result<()> {
+///         self.0.lock().unwrap().write_labels(batch)
+///     }
+///     fn write_masters(&mut self, batch: &[Master]) -> Result<()> {
+///         self.0.lock().unwrap().write_masters(batch)
+///     }
+///     fn write_releases(&mut self, batch: &[Release]) -> Result<()> {
+///         self.0.lock().unwrap().write_releases(batch)
+///     }
+/// }
+///
+/// let file = File::open("test_data/releases.xml").unwrap();
+/// let releases = ReleaseReader::new(BufReader::new(file))
+///     .collect::<Result<Vec<_>>>()
+///     .unwrap();
+///
+/// let mut serial = CountingSink::default();
+/// for batch in releases.chunks(1) {
+///     serial.write_releases(batch).unwrap();
+/// }
+///
+/// let counted = Arc::new(Mutex::new(CountingSink::default()));
+/// thread::scope(|scope| {
+///     let mut sink = ThreadedSink::new(scope, 2, || Shared(counted.clone()));
+///     for batch in releases.chunks(1) {
+///         sink.write_releases(batch).unwrap();
+///     }
+///     sink.join().unwrap();
+/// });
+/// assert_eq!(counted.lock().unwrap().row_counts(), serial.row_counts());
+/// assert_eq!(serial.row_counts()["release"], 3);
+/// ```
+pub struct ThreadedSink<'scope> {
+    sender: Option<SyncSender<Batch>>,
+    writers: Vec<ScopedJoinHandle<'scope, Result<()>>>,
+    /// Set by the writer that failed, stopping the others.
+    failed: Arc<AtomicBool>,
+}
+
+impl<'scope> ThreadedSink<'scope> {
+    /// Start `threads` writers in the scope, creating their sinks with `sink`.
+    pub fn new<'env, S, F>(scope: &'scope Scope<'scope, 'env>, threads: usize, sink: F) -> Self
+    where
+        S: Sink,
+        F: Fn() -> S + Send + Sync + 'scope,
+    {
+        let (sender, receiver) = sync_channel::<Batch>(threads);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let sink = Arc::new(sink);
+        let failed = Arc::new(AtomicBool::new(false));
+        let writers = (0..threads)
+            .map(|_| {
+                let (receiver, sink, failed) = (receiver.clone(), sink.clone(), failed.clone());
+                scope.spawn(move || {
+                    let mut sink = sink();
+                    loop {
+                        // the lock is released before the batch is written,
+                        // so the other writers can take the next ones
+                        let batch = receiver.lock().unwrap().recv();
+                        let batch = match batch {
+                            Ok(batch) => batch,
+                            // the batches end with the sender dropped
+                            Err(_) => break,
+                        };
+                        if failed.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        if let Err(e) = batch.write(&mut sink) {
+                            failed.store(true, Ordering::Relaxed);
+                            return Err(e);
+                        }
+                    }
+                    sink.flush()
+                })
+            })
+            .collect();
+        ThreadedSink {
+            sender: Some(sender),
+            writers,
+            failed,
+        }
+    }
+
+    fn send(&mut self, batch: Batch) -> Result<()> {
+        let sender = match &self.sender {
+            Some(sender) => sender,
+            None => {
+                return Err(DiscogsLoadError::Config(
+                    "A batch was written after the writer threads were joined".to_string(),
+                ))
+            }
+        };
+        if !self.failed.load(Ordering::Relaxed) && sender.send(batch).is_ok() {
+            return Ok(());
+        }
+        // the error of the writer that failed, the batch isn't written
+        self.join()?;
+        Err(DiscogsLoadError::Config(
+            "The writer threads stopped before the batch was written".to_string(),
+        ))
+    }
+
+    /// Wait for the writers to write the batches sent, and return the error
+    /// of the one that failed.
+    pub fn join(&mut self) -> Result<()> {
+        self.sender = None;
+        let mut result = Ok(());
+        for writer in self.writers.drain(..) {
+            let written = writer
+                .join()
+                .unwrap_or_else(|e| std::panic::resume_unwind(e));
+            if result.is_ok() {
+                result = written;
+            }
+        }
+        result
+    }
+}
+
+impl<'scope> Sink for ThreadedSink<'scope> {
+    fn write_artists(&mut self, batch: &[Artist]) -> Result<()> {
+        self.send(Batch::Artists(batch.to_vec()))
+    }
+
+    fn write_labels(&mut self, batch: &[Label]) -> Result<()> {
+        self.send(Batch::Labels(batch.to_vec()))
+    }
+
+    fn write_masters(&mut self, batch: &[Master]) -> Result<()> {
+        self.send(Batch::Masters(batch.to_vec()))
+    }
+
+    fn write_releases(&mut self, batch: &[Release]) -> Result<()> {
+        self.send(Batch::Releases(batch.to_vec()))
+    }
+
+    fn take_artists(&mut self, batch: Vec<Artist>) -> Result<()> {
+        self.send(Batch::Artists(batch))
+    }
+
+    fn take_labels(&mut self, batch: Vec<Label>) -> Result<()> {
+        self.send(Batch::Labels(batch))
+    }
+
+    fn take_masters(&mut self, batch: Vec<Master>) -> Result<()> {
+        self.send(Batch::Masters(batch))
+    }
+
+    fn take_releases(&mut self, batch: Vec<Release>) -> Result<()> {
+        self.send(Batch::Releases(batch))
+    }
+}
@@ -0,0 +1,187 @@
+use log::{info, warn};
+use postgres::types::Type;
+use rusqlite::{params_from_iter, types, Connection};
+use std::{collections::BTreeMap, path::Path};
+
+use crate::artist::Artist;
+use crate::db::{DbOpt, SqlSerialization, TableColumns, Value};
+use crate::error::Result;
+use crate::label::Label;
+use crate::master::Master;
+use crate::parser::{Entity, Record};
+use crate::release::Release;
+use crate::sink::{Sink, TableWriter};
+
+/// Bound parameters of a statement, SQLite's limit since 3.32.
+const MAX_PARAMETERS: usize = 32766;
+/// Rows inserted by one statement, unless their parameters exceed the limit.
+const ROWS_PER_STATEMENT: usize = 500;
+
+/// Loads the batches into the tables of a SQLite database, for
+/// `--backend sqlite`, with the same columns as the Postgres tables.
+///
+/// The tables of an entity are created with its first batch, replacing the
+/// ones of an earlier load unless `--append` is given. Arrays are stored as
+/// JSON text, `["Rock","Hard Rock"]`, and booleans as 0 and 1.
+pub struct SqliteSink {
+    conn: Connection,
+    columns: TableColumns,
+    append: bool,
+    created: Vec<Entity>,
+    rows: BTreeMap<String, u64>,
+}
+
+impl SqliteSink {
+    /// Open the database file, it is created when it doesn't exist.
+    pub fn open(path: &Path, db_opts: &DbOpt) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        // the statements of all tables, of full and of last chunks
+        conn.set_prepared_statement_cache_capacity(64);
+        Ok(SqliteSink {
+            conn,
+            columns: TableColumns::new(db_opts),
+            append: db_opts.append,
+            created: Vec::new(),
+            rows: BTreeMap::new(),
+        })
+    }
+
+    /// Write the tables of a batch in a single transaction.
+    fn write_batch<F>(&mut self, entity: Entity, write: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self) -> Result<()>,
+    {
+        self.create_tables(entity)?;
+        self.conn.execute_batch("BEGIN")?;
+        if let Err(e) = write(self) {
+            if let Err(rollback) = self.conn.execute_batch("ROLLBACK") {
+                warn!("Could not roll back the failed batch: {}", rollback);
+            }
+            return Err(e);
+        }
+        self.conn.execute_batch("COMMIT")?;
+        Ok(())
+    }
+
+    fn create_tables(&mut self, entity: Entity) -> Result<()> {
+        if self.created.contains(&entity) {
+            return Ok(());
+        }
+        info!("Creating the tables.");
+        let mut sql = String::new();
+        for (i, table) in entity.tables().iter().enumerate() {
+            // the rows of the child tables get an id, like the serial of
+            // the Postgres tables
+            let mut columns = if i > 0 {
+                vec!["id INTEGER PRIMARY KEY".to_string()]
+            } else {
+                Vec::new()
+            };
+            for (column, col_type) in self.columns.columns(table) {
+                columns.push(format!("{} {}", column, column_type(col_type)));
+            }
+            if !self.append {
+                sql.push_str(&format!("DROP TABLE IF EXISTS {};\n", table));
+            }
+            sql.push_str(&format!(
+                "CREATE TABLE IF NOT EXISTS {} (\n    {}\n);\n",
+                table,
+                columns.join(",\n    ")
+            ));
+        }
+        self.conn.execute_batch(&sql)?;
+        self.created.push(entity);
+        Ok(())
+    }
+
+    /// Insert the rows whose values are given one after the other with a
+    /// single statement.
+    fn insert(&self, table: &str, values: &[types::Value]) -> Result<()> {
+        let columns = self.columns.columns(table);
+        let row = format!("({})", vec!["?"; columns.len()].join(", "));
+        let names: Vec<&str> = columns.iter().map(|(column, _)| *column).collect();
+        let statement = format!(
+            "INSERT INTO {} ({}) VALUES {}",
+            table,
+            names.join(", "),
+            vec![row; values.len() / columns.len()].join(", ")
+        );
+        self.conn
+            .prepare_cached(&statement)?
+            .execute(params_from_iter(values))?;
+        Ok(())
+    }
+}
+
+impl TableWriter for SqliteSink {
+    fn write_rows(
+        &mut self,
+        table: &'static str,
+        rows: &mut dyn Iterator<Item = &dyn SqlSerialization>,
+    ) -> Result<()> {
+        let columns = self.columns.columns(table).len();
+        let chunk = columns * (MAX_PARAMETERS / columns).min(ROWS_PER_STATEMENT);
+        let mut values = Vec::with_capacity(chunk);
+        let mut written = 0;
+        for row in rows {
+            values.extend(
+                self.columns
+                    .values(table, row)?
+                    .into_iter()
+                    .map(sqlite_value),
+            );
+            written += 1;
+            if values.len() == chunk {
+                self.insert(table, &values)?;
+                values.clear();
+            }
+        }
+        if !values.is_empty() {
+            self.insert(table, &values)?;
+        }
+        *self.rows.entry(table.to_string()).or_insert(0) += written;
+        Ok(())
+    }
+}
+
+impl Sink for SqliteSink {
+    fn write_artists(&mut self, batch: &[Artist]) -> Result<()> {
+        self.write_batch(Entity::Artist, |db| Artist::write_tables(batch, db))
+    }
+
+    fn write_labels(&mut self, batch: &[Label]) -> Result<()> {
+        self.write_batch(Entity::Label, |db| Label::write_tables(batch, db))
+    }
+
+    fn write_masters(&mut self, batch: &[Master]) -> Result<()> {
+        self.write_batch(Entity::Master, |db| Master::write_tables(batch, db))
+    }
+
+    fn write_releases(&mut self, batch: &[Release]) -> Result<()> {
+        self.write_batch(Entity::Release, |db| Release::write_tables(batch, db))
+    }
+
+    fn row_counts(&self) -> BTreeMap<String, u64> {
+        self.rows.clone()
+    }
+}
+
+/// The SQLite type of a column of the Postgres tables.
+fn column_type(col_type: &Type) -> &'static str {
+    match *col_type {
+        Type::INT8 | Type::INT4 | Type::BOOL => "INTEGER",
+        _ => "TEXT",
+    }
+}
+
+fn sqlite_value(value: Value) -> types::Value {
+    match value {
+        Value::Null => types::Value::Null,
+        Value::Int(n) => types::Value::Integer(n),
+        Value::Bool(b) => types::Value::Integer(b.into()),
+        Value::Text(text) => types::Value::Text(text),
+        Value::TextArray(elements) => {
+            types::Value::Text(serde_json::Value::from(elements).to_string())
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::{collections::HashSet, iter::Peekable, str::Chars, str::FromStr};
+
+/// A record whose text columns can be rewritten by `--transform` rules, and
+/// whose array columns deduplicated by `--dedup-arrays`.
+pub trait Transformable {
+    /// The text column `name`, if the record has it and it isn't NULL.
+    fn text_field(&mut self, name: &str) -> Option<&mut String>;
+
+    /// The array columns of the record.
+    fn text_arrays(&mut self) -> Vec<&mut Vec<String>>;
+}
+
+/// A `--transform` rule like `country=upper(country)`, assigning the value
+/// of an expression to a text column of every record having that column.
+#[derive(Debug, Clone)]
+pub struct Transform {
+    field: String,
+    expr: Expr,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Field(String),
+    Literal(String),
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Function {
+    Upper,
+    Lower,
+    Trim,
+    Replace,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<(Self, usize)> {
+        match name {
+            "upper" => Some((Function::Upper, 1)),
+            "lower" => Some((Function::Lower, 1)),
+            "trim" => Some((Function::Trim, 1)),
+            "replace" => Some((Function::Replace, 3)),
+            _ => None,
+        }
+    }
+}
+
+impl Transform {
+    /// Rewrite the record, rules referring to a column it doesn't have or
+    /// that is NULL are left out.
+    pub fn apply<T: Transformable>(&self, record: &mut T) {
+        if let Some(value) = self.expr.eval(record) {
+            if let Some(field) = record.text_field(&self.field) {
+                *field = value;
+            }
+        }
+    }
+}
+
+/// Apply the rules in the order they were given.
+pub fn apply_all<T: Transformable>(transforms: &[Transform], record: &mut T) {
+    for transform in transforms {
+        transform.apply(record);
+    }
+}
+
+/// Drop the repeated values of the record's array columns for
+/// `--dedup-arrays`, the first of them stays where it was.
+pub fn dedup_arrays<T: Transformable>(record: &mut T) {
+    for values in record.text_arrays() {
+        let mut seen = HashSet::new();
+        values.retain(|value| seen.insert(value.clone()));
+    }
+}
+
+impl Expr {
+    fn eval<T: Transformable>(&self, record: &mut T) -> Option<String> {
+        match self {
+            Expr::Field(name) => record.text_field(name).map(|value| value.clone()),
+            Expr::Literal(value) => Some(value.clone()),
+            Expr::Call(function, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(record))
+                    .collect::<Option<Vec<String>>>()?;
+                Some(match function {
+                    Function::Upper => args[0].to_uppercase(),
+                    Function::Lower => args[0].to_lowercase(),
+                    Function::Trim => args[0].trim().to_string(),
+                    Function::Replace => args[0].replace(&args[1], &args[2]),
+                })
+            }
+        }
+    }
+}
+
+impl FromStr for Transform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (field, expr) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <column>=<expression>: {}", s))?;
+        let field = field.trim();
+        if field.is_empty() || !field.chars().all(is_identifier) {
+            return Err(format!("invalid column name: {}", field));
+        }
+        let mut chars = expr.chars().peekable();
+        let expr = parse_expr(&mut chars)?;
+        skip_whitespace(&mut chars);
+        if let Some(c) = chars.next() {
+            return Err(format!("unexpected '{}' in: {}", c, s));
+        }
+        Ok(Transform {
+            field: field.to_string(),
+            expr,
+        })
+    }
+}
+
+fn is_identifier(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+/// expr := column | 'text' | "text" | function(expr, ...)
+fn parse_expr(chars: &mut Peekable<Chars>) -> Result<Expr, String> {
+    skip_whitespace(chars);
+    match chars.peek().copied() {
+        Some(quote @ '\'') | Some(quote @ '"') => {
+            chars.next();
+            let mut literal = String::new();
+            loop {
+                match chars.next() {
+                    Some(c) if c == quote => return Ok(Expr::Literal(literal)),
+                    Some(c) => literal.push(c),
+                    None => return Err(format!("unterminated string: {}", literal)),
+                }
+            }
+        }
+        Some(c) if is_identifier(c) => {
+            let mut name = String::new();
+            while let Some(c) = chars.next_if(|c| is_identifier(*c)) {
+                name.push(c);
+            }
+            skip_whitespace(chars);
+            if chars.peek() != Some(&'(') {
+                return Ok(Expr::Field(name));
+            }
+            chars.next();
+            let (function, arity) =
+                Function::from_name(&name).ok_or_else(|| format!("unknown function: {}", name))?;
+            let mut args = Vec::new();
+            skip_whitespace(chars);
+            if chars.peek() == Some(&')') {
+                chars.next();
+            } else {
+                loop {
+                    args.push(parse_expr(chars)?);
+                    skip_whitespace(chars);
+                    match chars.next() {
+                        Some(',') => {}
+                        Some(')') => break,
+                        _ => {
+                            return Err(format!("expected ',' or ')' in the arguments of {}", name))
+                        }
+                    }
+                }
+            }
+            if args.len() != arity {
+                return Err(format!(
+                    "{} takes {} arguments, got {}",
+                    name,
+                    arity,
+                    args.len()
+                ));
+            }
+            Ok(Expr::Call(function, args))
+        }
+        Some(c) => Err(format!("unexpected '{}'", c)),
+        None => Err("missing expression".to_string()),
+    }
+}
@@ -0,0 +1,219 @@
+use postgres::{
+    types::{Kind, Type},
+    Client,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryInto,
+    io::Read,
+};
+
+use crate::error::{DiscogsLoadError, Result};
+use crate::parser::Entity;
+
+/// Compares the records of a dump with a sample of the rows of their tables
+/// for `--verify-against`, instead of writing them.
+///
+/// Both sides are compared in the binary COPY format, the rows of the dump
+/// encoded like they would be loaded and the rows of the database copied out
+/// of the tables, so the values have to match byte for byte.
+#[derive(Debug)]
+pub struct Verifier {
+    sample_size: usize,
+    /// The sampled ids of every table and whether the dump had them.
+    samples: HashMap<String, BTreeMap<i64, bool>>,
+    mismatches: Vec<String>,
+}
+
+impl Verifier {
+    pub fn new(sample_size: usize) -> Self {
+        Verifier {
+            sample_size,
+            samples: HashMap::new(),
+            mismatches: Vec::new(),
+        }
+    }
+
+    /// Whether the rows of the table are verified, only the tables of the
+    /// records themselves are sampled by id.
+    pub fn verifies(table: &str) -> bool {
+        Entity::ALL.iter().any(|entity| entity.tables()[0] == table)
+    }
+
+    /// The ids of the table to compare, drawn from the database the first
+    /// time the table is seen.
+    pub fn sample(&mut self, client: &mut Client, table: &str) -> Result<Vec<i64>> {
+        if !self.samples.contains_key(table) {
+            let ids = client
+                .query(
+                    format!("SELECT id FROM {} ORDER BY random() LIMIT $1", table).as_str(),
+                    &[&(self.sample_size as i64)],
+                )?
+                .iter()
+                .map(|row| (row.get(0), false))
+                .collect();
+            self.samples.insert(table.to_string(), ids);
+        }
+        Ok(self.samples[table].keys().copied().collect())
+    }
+
+    /// Compare the encoded rows of sampled ids with the rows in the table.
+    pub fn compare(
+        &mut self,
+        client: &mut Client,
+        table: &str,
+        columns: &[(&'static str, Type)],
+        rows: Vec<(i64, Vec<u8>)>,
+    ) -> Result<()> {
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let ids: Vec<String> = rows.iter().map(|(id, _)| id.to_string()).collect();
+        let names: Vec<&str> = columns.iter().map(|(name, _)| *name).collect();
+        let mut copied = Vec::new();
+        client
+            .copy_out(
+                format!(
+                    "COPY (SELECT {} FROM {} WHERE id IN ({})) TO STDOUT BINARY",
+                    names.join(", "),
+                    table,
+                    ids.join(", ")
+                )
+                .as_str(),
+            )?
+            .read_to_end(&mut copied)?;
+        let mut stored = HashMap::new();
+        for row in copied_rows(&copied)? {
+            let id = match row.first() {
+                Some(Some(id)) => i64::from_be_bytes(field_bytes(id)?),
+                _ => return Err(copy_error("row without an id")),
+            };
+            stored.insert(id, row);
+        }
+
+        let sample = self.samples.get_mut(table).unwrap();
+        for (id, encoded) in &rows {
+            sample.insert(*id, true);
+            let stored = match stored.get(id) {
+                Some(stored) => stored,
+                None => {
+                    self.mismatches
+                        .push(format!("{} {}: missing from the table", table, id));
+                    continue;
+                }
+            };
+            let dumped = fields(encoded)?.0;
+            let differing: Vec<&str> = columns
+                .iter()
+                .zip(dumped.iter().zip(stored))
+                .filter(|((_, col_type), (dumped, stored))| !same_value(col_type, dumped, stored))
+                .map(|((column, _), _)| *column)
+                .collect();
+            if !differing.is_empty() {
+                self.mismatches.push(format!(
+                    "{} {}: different {}",
+                    table,
+                    id,
+                    differing.join(", ")
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// The mismatches found, and the sampled rows the dump didn't have.
+    pub fn report(&self) -> Vec<String> {
+        let mut report = self.mismatches.clone();
+        for (table, sample) in &self.samples {
+            for (id, _) in sample.iter().filter(|(_, seen)| !**seen) {
+                report.push(format!("{} {}: not in the dump", table, id));
+            }
+        }
+        report
+    }
+
+    /// Number of rows compared so far.
+    pub fn compared(&self) -> usize {
+        self.samples
+            .values()
+            .map(|sample| sample.values().filter(|seen| **seen).count())
+            .sum()
+    }
+}
+
+/// Whether two encoded values are equal. Postgres sends an empty array
+/// without dimensions, while it is encoded with a single empty one.
+fn same_value(col_type: &Type, a: &Option<&[u8]>, b: &Option<&[u8]>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) if matches!(col_type.kind(), Kind::Array(_)) => {
+            a == b || (empty_array(a) && empty_array(b))
+        }
+        _ => a == b,
+    }
+}
+
+/// An array header of no dimensions, or of one of length 0, without elements.
+fn empty_array(value: &[u8]) -> bool {
+    match value.get(..4) {
+        Some([0, 0, 0, 0]) => true,
+        Some([0, 0, 0, 1]) => value.len() == 20 && value[12..16] == [0, 0, 0, 0],
+        _ => false,
+    }
+}
+
+type Fields<'a> = Vec<Option<&'a [u8]>>;
+
+/// The id of an encoded row of a record table, its first column.
+pub fn row_id(encoded: &[u8]) -> Result<i64> {
+    match fields(encoded)?.0.first() {
+        Some(Some(id)) => Ok(i64::from_be_bytes(field_bytes(id)?)),
+        _ => Err(copy_error("row without an id")),
+    }
+}
+
+/// The rows of a binary COPY stream, after its header and up to its trailer.
+fn copied_rows(copied: &[u8]) -> Result<Vec<Fields<'_>>> {
+    let header = crate::db::COPY_HEADER.len();
+    if copied.len() < header {
+        return Err(copy_error("missing header"));
+    }
+    let mut rows = Vec::new();
+    let mut rest = &copied[header..];
+    while !rest.starts_with(crate::db::COPY_TRAILER) {
+        let (row, len) = fields(rest)?;
+        rows.push(row);
+        rest = &rest[len..];
+    }
+    Ok(rows)
+}
+
+/// The fields of the row at the start of `data`, NULL being `None`, and the
+/// length of the row.
+fn fields(data: &[u8]) -> Result<(Fields<'_>, usize)> {
+    let count = i16::from_be_bytes(field_bytes(data.get(..2).unwrap_or_default())?);
+    let mut pos = 2;
+    let mut fields = Vec::new();
+    for _ in 0..count {
+        let len = i32::from_be_bytes(field_bytes(data.get(pos..pos + 4).unwrap_or_default())?);
+        pos += 4;
+        if len < 0 {
+            fields.push(None);
+            continue;
+        }
+        let end = pos + len as usize;
+        fields.push(Some(
+            data.get(pos..end)
+                .ok_or_else(|| copy_error("truncated field"))?,
+        ));
+        pos = end;
+    }
+    Ok((fields, pos))
+}
+
+fn field_bytes<const N: usize>(bytes: &[u8]) -> Result<[u8; N]> {
+    bytes.try_into().map_err(|_| copy_error("truncated row"))
+}
+
+fn copy_error(message: &str) -> DiscogsLoadError {
+    DiscogsLoadError::Value(format!("Unexpected COPY data: {}", message))
+}
@@ -0,0 +1,28 @@
+-- Child rows to their records
+ALTER TABLE release_artist ADD CONSTRAINT fkey_release_artist_release FOREIGN KEY (release_id) REFERENCES release (id);
+ALTER TABLE release_extraartist ADD CONSTRAINT fkey_release_extraartist_release FOREIGN KEY (release_id) REFERENCES release (id);
+ALTER TABLE release_company ADD CONSTRAINT fkey_release_company_release FOREIGN KEY (release_id) REFERENCES release (id);
+ALTER TABLE release_format ADD CONSTRAINT fkey_release_format_release FOREIGN KEY (release_id) REFERENCES release (id);
+ALTER TABLE release_label ADD CONSTRAINT fkey_release_label_release FOREIGN KEY (release_id) REFERENCES release (id);
+ALTER TABLE release_track ADD CONSTRAINT fkey_release_track_release FOREIGN KEY (release_id) REFERENCES release (id);
+ALTER TABLE release_track_extraartist ADD CONSTRAINT fkey_release_track_extraartist_release FOREIGN KEY (release_id) REFERENCES release (id);
+ALTER TABLE release_identifier ADD CONSTRAINT fkey_release_identifier_release FOREIGN KEY (release_id) REFERENCES release (id);
+ALTER TABLE release_video ADD CONSTRAINT fkey_release_video_release FOREIGN KEY (release_id) REFERENCES release (id);
+
+ALTER TABLE master_artist ADD CONSTRAINT fkey_master_artist_master FOREIGN KEY (master_id) REFERENCES master (id);
+ALTER TABLE master_video ADD CONSTRAINT fkey_master_video_master FOREIGN KEY (master_id) REFERENCES master (id);
+
+ALTER TABLE artist_member ADD CONSTRAINT fkey_artist_member_artist FOREIGN KEY (artist_id) REFERENCES artist (id);
+ALTER TABLE artist_alias ADD CONSTRAINT fkey_artist_alias_artist FOREIGN KEY (artist_id) REFERENCES artist (id);
+
+-- References between the dumps, which the Discogs data leaves dangling
+ALTER TABLE release ADD CONSTRAINT fkey_release_master FOREIGN KEY (master_id) REFERENCES master (id);
+ALTER TABLE release_label ADD CONSTRAINT fkey_release_label_label FOREIGN KEY (label_id) REFERENCES label (id);
+ALTER TABLE release_artist ADD CONSTRAINT fkey_release_artist_artist FOREIGN KEY (artist_id) REFERENCES artist (id);
+ALTER TABLE release_extraartist ADD CONSTRAINT fkey_release_extraartist_artist FOREIGN KEY (artist_id) REFERENCES artist (id);
+ALTER TABLE release_company ADD CONSTRAINT fkey_release_company_label FOREIGN KEY (company_id) REFERENCES label (id);
+ALTER TABLE release_track_extraartist ADD CONSTRAINT fkey_release_track_extraartist_artist FOREIGN KEY (artist_id) REFERENCES artist (id);
+ALTER TABLE master ADD CONSTRAINT fkey_master_release FOREIGN KEY (release_id) REFERENCES release (id);
+ALTER TABLE master_artist ADD CONSTRAINT fkey_master_artist_artist FOREIGN KEY (artist_id) REFERENCES artist (id);
+ALTER TABLE artist_member ADD CONSTRAINT fkey_artist_member_member FOREIGN KEY (member_id) REFERENCES artist (id);
+ALTER TABLE artist_alias ADD CONSTRAINT fkey_artist_alias_alias FOREIGN KEY (alias_id) REFERENCES artist (id);
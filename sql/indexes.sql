@@ -1,16 +1,37 @@
 -- Primary keys
 ALTER TABLE release ADD CONSTRAINT pkey_release PRIMARY KEY (id);
+ALTER TABLE master ADD CONSTRAINT pkey_master PRIMARY KEY (id);
+ALTER TABLE artist ADD CONSTRAINT pkey_artist PRIMARY KEY (id);
+ALTER TABLE label ADD CONSTRAINT pkey_label PRIMARY KEY (id);
 -- ALTER TABLE release_video ADD CONSTRAINT pkey_release_video PRIMARY KEY (release_id);
 -- ALTER TABLE release_label ADD CONSTRAINT pkey_release_label PRIMARY KEY (release_id);
 
 -- Indexes
-CREATE INDEX idx_label on label(id);
+CREATE INDEX idx_artist_member_artist on artist_member(artist_id);
+CREATE INDEX idx_artist_member_member on artist_member(member_id);
+CREATE INDEX idx_artist_alias_artist on artist_alias(artist_id);
+CREATE INDEX idx_artist_alias_alias on artist_alias(alias_id);
 
-CREATE INDEX idx_artist on artist(id);
-
-CREATE INDEX idx_release on release(id);
 CREATE INDEX idx_release_video on release_video(release_id);
+CREATE INDEX idx_release_artist on release_artist(release_id);
+CREATE INDEX idx_release_artist_artist on release_artist(artist_id);
+CREATE INDEX idx_release_extraartist on release_extraartist(release_id);
+CREATE INDEX idx_release_extraartist_artist on release_extraartist(artist_id);
+CREATE INDEX idx_release_company on release_company(release_id);
+CREATE INDEX idx_release_company_company on release_company(company_id);
+CREATE INDEX idx_release_format on release_format(release_id);
 CREATE INDEX idx_release_label on release_label(release_id);
+CREATE INDEX idx_release_track on release_track(release_id);
+CREATE INDEX idx_release_track_extraartist on release_track_extraartist(release_id);
+CREATE INDEX idx_release_track_extraartist_artist on release_track_extraartist(artist_id);
+CREATE INDEX idx_release_identifier on release_identifier(release_id);
 
 CREATE INDEX idx_master_artist_master on master_artist(master_id);
-CREATE INDEX idx_master_artist_artist on master_artist(artist_id);
\ No newline at end of file
+CREATE INDEX idx_master_artist_artist on master_artist(artist_id);
+CREATE INDEX idx_master_video on master_video(master_id);
+
+-- Array columns, for queries like genres @> '{Electronic}'
+CREATE INDEX idx_release_genres on release USING gin (genres);
+CREATE INDEX idx_release_styles on release USING gin (styles);
+CREATE INDEX idx_master_genres on master USING gin (genres);
+CREATE INDEX idx_master_styles on master USING gin (styles);
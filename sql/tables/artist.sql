@@ -1,7 +1,9 @@
-DROP TABLE IF EXISTS artist;
+DROP TABLE IF EXISTS artist CASCADE;
+DROP TABLE IF EXISTS artist_member CASCADE;
+DROP TABLE IF EXISTS artist_alias CASCADE;
 
 CREATE TABLE artist (
-    id int not null,
+    id bigint not null,
     name text,
     real_name text,
     profile text,
@@ -9,5 +11,19 @@ CREATE TABLE artist (
     name_variations text[],
     urls text[],
     aliases text[],
-    members text[]
+    members text[],
+    groups text[],
+    source_offset bigint
+);
+
+CREATE TABLE artist_member (
+    artist_id bigint NOT NULL,
+    member_id bigint NOT NULL,
+    member_name text
+);
+
+CREATE TABLE artist_alias (
+    artist_id bigint NOT NULL,
+    alias_id bigint NOT NULL,
+    alias_name text
 );
@@ -1,12 +1,13 @@
-DROP TABLE IF EXISTS label;
+DROP TABLE IF EXISTS label CASCADE;
 
 CREATE TABLE label (
-    id int not null,
+    id bigint not null,
 	name text,
 	contactinfo text,
 	profile text,
     parent_label text,
     sublabels text[],
     urls text[],
-    data_quality text
+    data_quality text,
+    source_offset bigint
 );
\ No newline at end of file
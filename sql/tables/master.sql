@@ -1,21 +1,31 @@
 DROP TABLE IF EXISTS master CASCADE;
 DROP TABLE IF EXISTS master_artist CASCADE;
+DROP TABLE IF EXISTS master_video CASCADE;
 
 CREATE TABLE master (
-    id integer NOT NULL,
+    id bigint NOT NULL,
     title text,
-    release_id integer NOT NULL,
+    release_id bigint,
     year integer,
     notes text,
     genres text[],
     styles text[],
-    data_quality text
+    data_quality text,
+    source_offset bigint
  );
 
  CREATE TABLE master_artist (
-    artist_id integer NOT NULL,
-    master_id integer NOT NULL,
+    artist_id bigint NOT NULL,
+    master_id bigint NOT NULL,
     name text,
     anv text,
     role text
-);
\ No newline at end of file
+);
+
+CREATE TABLE master_video (
+    id serial,
+    master_id bigint NOT NULL,
+    duration integer,
+    src text,
+    title text
+);
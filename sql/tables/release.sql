@@ -1,9 +1,16 @@
 DROP TABLE IF EXISTS release CASCADE;
+DROP TABLE IF EXISTS release_artist CASCADE;
+DROP TABLE IF EXISTS release_extraartist CASCADE;
+DROP TABLE IF EXISTS release_company CASCADE;
+DROP TABLE IF EXISTS release_format CASCADE;
 DROP TABLE IF EXISTS release_label CASCADE;
+DROP TABLE IF EXISTS release_track CASCADE;
+DROP TABLE IF EXISTS release_track_extraartist CASCADE;
+DROP TABLE IF EXISTS release_identifier CASCADE;
 DROP TABLE IF EXISTS release_video CASCADE;
 
 CREATE TABLE release (
-    id int NOT NULL,
+    id bigint NOT NULL,
     status text,
     title text,
     country text,
@@ -11,21 +18,91 @@ CREATE TABLE release (
     notes text,
     genres text[],
     styles text[],
-    master_id int,
-    data_quality text
+    master_id bigint,
+    is_main_release boolean,
+    barcode text,
+    matrix text,
+    is_compilation boolean,
+    data_quality text,
+    source_offset bigint
+);
+
+CREATE TABLE release_artist (
+    id serial,
+    release_id bigint NOT NULL,
+    position int,
+    artist_id bigint,
+    name text,
+    anv text,
+    join_string text,
+    role text
+);
+
+CREATE TABLE release_extraartist (
+    id serial,
+    release_id bigint NOT NULL,
+    artist_id bigint,
+    name text,
+    anv text,
+    role text
+);
+
+CREATE TABLE release_company (
+    id serial,
+    release_id bigint NOT NULL,
+    company_id bigint,
+    name text,
+    entity_type int,
+    entity_type_name text,
+    catno text
+);
+
+CREATE TABLE release_format (
+    id serial,
+    release_id bigint NOT NULL,
+    name text,
+    qty int,
+    text text,
+    descriptions text[]
 );
 
 CREATE TABLE release_label (
     id serial,
-    release_id int NOT NULL,
-    label_id int,
+    release_id bigint NOT NULL,
+    label_id bigint,
     label text,
     catno text
 );
 
+CREATE TABLE release_track (
+    id serial,
+    release_id bigint NOT NULL,
+    position text,
+    title text,
+    duration text,
+    is_heading boolean
+);
+
+CREATE TABLE release_track_extraartist (
+    id serial,
+    release_id bigint NOT NULL,
+    position text,
+    artist_id bigint,
+    name text,
+    role text
+);
+
+CREATE TABLE release_identifier (
+    id serial,
+    release_id bigint NOT NULL,
+    type text,
+    description text,
+    value text
+);
+
 CREATE TABLE release_video (
     id serial,
-    release_id int NOT NULL,
+    release_id bigint NOT NULL,
     duration int,
     src text,
     title text